@@ -1,77 +1,188 @@
+use std::time::Instant;
+
 use serde::{Serialize, Deserialize};
 use midi_msg::{MidiMsg, ChannelVoiceMsg, ChannelModeMsg};
 
 use crate::midi::*;
-use crate::graphics::RGB;
+use crate::graphics::{RGB, Tint};
+use crate::transport::{TimeSpan, Speed};
 
 #[derive(PartialEq, Serialize, Deserialize)]
 #[serde(tag = "style")]
 pub enum ChordStyle {
     #[serde(rename = "plain")]
     Plain,
+    /// fades out over time
     #[serde(rename = "decay")]
     Decay{ 
-        time: u32 
+        time: TimeSpan 
     },
+    /// fades out over time once the note's let go
     #[serde(rename = "decay_release")]
     DecayRelease{
-        time: u32
+        time: TimeSpan
     },
+    /// px per second, or how long it takes to get to its full width
     #[serde(rename = "grow")]
     Grow{
-        speed: u32
+        speed: Speed
     },
+    /// px per second, or how long it takes to cross the window
     #[serde(rename = "marquee")]
     Marquee{
-        speed: u32
+        speed: Speed
     },
 }
 
+#[derive(PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub enum ZoneSide {
+    /// master on channel 1, members counting up from 2
+    #[serde(rename = "lower")]
+    Lower,
+    /// master on channel 16, members counting down from 15
+    #[serde(rename = "upper")]
+    Upper,
+}
+
+/// an mpe zone: every note gets its own member channel with its own bend, slide (cc74) and pressure
+#[derive(PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub struct MpeZone {
+    pub zone: ZoneSide,
+    /// how many member channels
+    pub members: u8,
+    /// semitones for a full per-note bend
+    #[serde(default = "default_mpe_bend_range")]
+    pub bend_range: f32,
+}
+impl MpeZone {
+    pub fn master(&self) -> Channel {
+        match self.zone {
+            ZoneSide::Lower => Channel::Ch1,
+            ZoneSide::Upper => Channel::Ch16,
+        }
+    }
+
+    pub fn members(&self) -> Vec<Channel> {
+        let count = self.members.clamp(1, 15);
+        (1..=count).map(|i| match self.zone {
+            ZoneSide::Lower => Channel::from(i),
+            ZoneSide::Upper => Channel::from(15 - i),
+        }).collect()
+    }
+}
+
+/// what aftertouch does to a note's bar
+#[derive(PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub enum PressureStyle {
+    #[serde(rename = "none")]
+    None,
+    /// lighter towards white
+    #[serde(rename = "brightness")]
+    Brightness,
+    /// taller bar
+    #[serde(rename = "thickness")]
+    Thickness,
+    /// a halo around the bar
+    #[serde(rename = "glow")]
+    Glow,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Chord {
+    /// keeps its state across reloads even if everything else about it changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// what it was filled in from, see template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// which of setup.included it's from, None if it's in the setup itself
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub from: Option<usize>,
     pub xpos: u32,
     pub width: u32,
     pub channel: Channel,
+    /// more channels to take notes from, pitch bend on these only moves their own notes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub note_channels: Vec<Channel>,
+    /// take an mpe zone instead, channel should be the zone's master
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mpe: Option<MpeZone>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
     pub notes: Vec<Note>,
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
     pub modulation: u16,
+    /// semitones for a full bend either way
+    #[serde(default = "default_bend_range")]
+    pub bend_range: f32,
+    /// current bend of each channel in semitones
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub bends: [f32; 16],
+    /// last cc74 of each channel
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub slides: [Option<u8>; 16],
+    /// last channel pressure of each channel, new notes start with it
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub pressures: [u8; 16],
+    /// damper pedal (cc64)
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub sustain: bool,
+    /// sostenuto pedal (cc66)
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub sostenuto: bool,
     pub velocity_sense: bool,
+    /// left out it's brightness for mpe chords and none for the rest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pressure_style: Option<PressureStyle>,
     pub style: ChordStyle,
     pub transpose: i32,
     pub color: RGB,
     pub shadow: RGB,
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub tint: Tint,
 }
 impl MidiProcessor for Chord{
-    fn deal_with(&mut self, message: MidiMsg) {
-        match message {
+    fn deal_with(&mut self, event: MidiEvent, now: Instant) {
+        if !event.is_from(&self.input) {
+            return;
+        }
+        let age = event.ms_ago(now);
+        let cc = event.control_change();
+        match event.msg {
             MidiMsg::ChannelVoice {channel, msg} => {
-                if channel as u8 == self.channel as u8 {
+                let channel = Channel::from(channel as u8);
+                if self.hears(channel) {
                     match msg {
                         ChannelVoiceMsg::NoteOn { note, velocity } => {
                             self.notes.push(
                                 Note {
                                     pitch: note,
                                     velocity,
-                                    age: 0
+                                    pressure: self.pressures[channel as usize],
+                                    channel,
+                                    age,
+                                    released: false,
+                                    sostenuto: false,
                                 }
                             );
                         },
                         ChannelVoiceMsg::NoteOff { note, velocity: _ } => {
-                            match self.style {
-                                ChordStyle::DecayRelease { time: _ } => {
-                                    // nop
-                                }
-                                ChordStyle::Marquee { speed: _ } => {
-                                    // nop 
-                                }
-                                _ => {
-                                    self.notes.retain(|x| x.pitch != note);
+                            for n in &mut self.notes {
+                                if n.pitch == note && n.channel == channel {
+                                    n.released = true;
                                 }
                             }
-                            
+                            self.let_go();
                         },
                         ChannelVoiceMsg::ControlChange { control } => {
                             match control {
@@ -80,6 +191,38 @@ impl MidiProcessor for Chord{
                                 },
                                 _ => {},
                             }
+                            match cc {
+                                Some((_, 64, value)) => self.set_sustain(value >= 64),
+                                Some((_, 66, value)) => self.set_sostenuto(value >= 64),
+                                Some((_, 74, value)) => self.slides[channel as usize] = Some(value),
+                                _ => {},
+                            }
+                        }
+                        ChannelVoiceMsg::PolyPressure { note, pressure } => {
+                            for n in &mut self.notes {
+                                if n.pitch == note && n.channel == channel {
+                                    n.pressure = pressure;
+                                }
+                            }
+                        }
+                        ChannelVoiceMsg::ChannelPressure { pressure } => {
+                            self.pressures[channel as usize] = pressure;
+                            // on the chord's own channel it's for every note
+                            let master = self.master_channel();
+                            for n in &mut self.notes {
+                                if channel == master || n.channel == channel {
+                                    n.pressure = pressure;
+                                }
+                            }
+                        }
+                        ChannelVoiceMsg::PitchBend { bend } => {
+                            // 14 bit, centred on 8192
+                            let amount = (bend as f32 - 8192.0) / 8192.0;
+                            let range = match self.mpe {
+                                Some(zone) if channel != zone.master() => zone.bend_range,
+                                _ => self.bend_range,
+                            };
+                            self.bends[channel as usize] = amount * range;
                         }
                         _ => {}
                     }
@@ -98,4 +241,123 @@ impl MidiProcessor for Chord{
         }
     }
 
+    fn subscriptions(&self) -> Vec<Subscription> {
+        self.channels().iter()
+            .map(|channel| Subscription::channel(*channel, &self.input))
+            .collect()
+    }
+
+}
+
+impl Chord {
+    /// what aftertouch does, whether or not it's been said
+    pub fn pressure_style(&self) -> PressureStyle {
+        match (self.pressure_style, &self.mpe) {
+            (Some(style), _) => style,
+            (None, Some(_)) => PressureStyle::Brightness,
+            (None, None) => PressureStyle::None,
+        }
+    }
+
+    /// what makes it the same chord after a reload
+    pub fn identity(&self) -> String {
+        match &self.id {
+            Some(id) => format!("id {}", id),
+            None => format!("ch {} {:?}", self.channel as u8 + 1, self.input),
+        }
+    }
+
+    /// take over whatever was playing on the old one
+    pub fn keep_state_from(&mut self, old: Chord) {
+        self.notes = old.notes;
+        self.modulation = old.modulation;
+        self.bends = old.bends;
+        self.slides = old.slides;
+        self.pressures = old.pressures;
+        self.sustain = old.sustain;
+        self.sostenuto = old.sostenuto;
+        self.tint = old.tint;
+    }
+
+    fn set_sustain(&mut self, down: bool) {
+        self.sustain = down;
+        self.let_go();
+    }
+
+    fn set_sostenuto(&mut self, down: bool) {
+        if down && !self.sostenuto {
+            // only catches what's held right now
+            for n in &mut self.notes {
+                n.sostenuto = !n.released;
+            }
+        }
+        self.sostenuto = down;
+        self.let_go();
+    }
+
+    /// drop released notes that no pedal is keeping any more
+    fn let_go(&mut self) {
+        match self.style {
+            ChordStyle::DecayRelease { time: _ } => {
+                // starts decaying instead, see Win::draw_chord
+            }
+            ChordStyle::Marquee { speed: _ } => {
+                // nop
+            }
+            _ => {
+                let (sustain, sostenuto) = (self.sustain, self.sostenuto);
+                self.notes.retain(|x| x.sounding(sustain, sostenuto));
+            }
+        }
+    }
+
+    /// every channel this chord takes notes from
+    pub fn channels(&self) -> Vec<Channel> {
+        let mut channels = vec![self.channel];
+        channels.extend(self.note_channels.iter().copied());
+        if let Some(zone) = &self.mpe {
+            channels.push(zone.master());
+            channels.extend(zone.members());
+        }
+        channels.dedup();
+        channels
+    }
+
+    pub fn hears(&self, channel: Channel) -> bool {
+        self.channels().contains(&channel)
+    }
+
+    /// bend, pressure etc on this channel affect every note
+    pub fn master_channel(&self) -> Channel {
+        match &self.mpe {
+            Some(zone) => zone.master(),
+            None => self.channel,
+        }
+    }
+
+    /// how far a note is bent in semitones, the master channel moves every note
+    pub fn bend_of(bends: &[f32; 16], master: Channel, note: &Note) -> f32 {
+        let mut bend = bends[master as usize];
+        if note.channel != master {
+            bend += bends[note.channel as usize];
+        }
+        bend
+    }
+
+    /// how far sideways a note's slide (cc74) moves it, -1.0 to 1.0
+    pub fn slide_of(slides: &[Option<u8>; 16], master: Channel, note: &Note) -> f32 {
+        let value = slides[note.channel as usize].or(slides[master as usize]);
+        match value {
+            Some(v) => (v as f32 - 64.0) / 64.0,
+            None => 0.0,
+        }
+    }
+}
+
+fn default_bend_range() -> f32 {
+    2.0
+}
+
+fn default_mpe_bend_range() -> f32 {
+    48.0
 }
\ No newline at end of file
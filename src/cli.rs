@@ -0,0 +1,94 @@
+use clap::{Parser, Subcommand};
+use minifb::Scale;
+
+use crate::input::PortSelector;
+use crate::migrate;
+use crate::setup::Setup;
+
+
+/// midi in, animation out
+#[derive(Parser)]
+#[command(name = "grooveanim")]
+pub struct Cli {
+    /// setup json to load (and save back to)
+    #[arg(short, long, default_value = "weirdgrv.json")]
+    pub setup: String,
+    /// midi input port, part of its name (or a regex) or its number from --list-ports.
+    /// overrides the setup
+    #[arg(short, long)]
+    pub port: Option<String>,
+    /// make our own midi port for a daw to connect to instead, --virtual=NAME to name it
+    // needs the = so "--virtual play" isn't a port called play
+    #[arg(long = "virtual", value_name = "NAME", num_args = 0..=1, require_equals = true, default_missing_value = "grooveanim")]
+    pub virtual_port: Option<String>,
+    /// at least 160, the editor needs the room
+    #[arg(long, default_value_t = 640, value_parser = clap::value_parser!(u32).range(160..))]
+    pub width: u32,
+    /// at least 120
+    #[arg(long, default_value_t = 480, value_parser = clap::value_parser!(u32).range(120..))]
+    pub height: u32,
+    /// window scale, 1 2 4 or 8
+    #[arg(long, default_value = "1", value_parser = parse_scale)]
+    pub scale: Scale,
+    /// step time by exactly 1/N s a frame instead of following the clock on the wall
+    #[arg(long)]
+    pub fps: Option<u32>,
+    /// the version a setup without one is taken to be. 2 for one saved with times
+    /// in ms from before there were versions, 1 (counting frames) otherwise
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=migrate::VERSION as i64))]
+    pub unversioned: u32,
+    /// when saving over a setup from an older version, keep the original as <setup>.v<N>.bak
+    #[arg(long)]
+    pub backup: bool,
+    /// the same as play FILE, from before there were modes
+    #[arg(long, value_name = "FILE")]
+    pub midi_file: Option<String>,
+    /// print the midi ports and exit
+    #[arg(long)]
+    pub list_ports: bool,
+    #[command(subcommand)]
+    pub mode: Option<Mode>,
+}
+
+#[derive(Subcommand)]
+pub enum Mode {
+    /// listen to midi ports (the default)
+    Live,
+    /// play a standard midi file instead of listening
+    Play {
+        file: String,
+    },
+    /// play a midi file with no window as fast as possible, saving every frame as a png
+    Render {
+        file: String,
+        /// folder for the frames
+        #[arg(short, long, default_value = "frames")]
+        out: String,
+    },
+}
+
+impl Cli {
+    /// what was given on the command line wins over the json,
+    /// but only while running, saving leaves the json's own alone
+    pub fn apply(&self, setup: &mut Setup) {
+        if let Some(name) = &self.virtual_port {
+            setup.port_override = Some(PortSelector::Virtual(name.clone()));
+        } else if let Some(port) = &self.port {
+            setup.port_override = Some(match port.parse::<usize>() {
+                Ok(i) => PortSelector::Index(i),
+                Err(_) => PortSelector::pattern(port),
+            });
+        }
+        setup.backup = self.backup;
+    }
+}
+
+fn parse_scale(s: &str) -> Result<Scale, String> {
+    match s {
+        "1" => Ok(Scale::X1),
+        "2" => Ok(Scale::X2),
+        "4" => Ok(Scale::X4),
+        "8" => Ok(Scale::X8),
+        _ => Err("scale should be 1, 2, 4 or 8".to_string()),
+    }
+}
@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+
+use crate::win::FRAME;
+
+
+/// what time it is as far as the rest of the program is concerned.
+/// it only moves when a frame's done, so everything in a frame sees the same now
+pub struct Clock {
+    start: Instant,
+    /// frames per second to step by, None follows the wall clock
+    fps: Option<u32>,
+    frames: u64,
+    now: Instant,
+    frame_time: Duration,
+}
+
+impl Clock {
+    /// keeps up with the wall clock, however long frames take
+    pub fn real_time() -> Self {
+        let start = Instant::now();
+        Clock {
+            start,
+            fps: None,
+            frames: 0,
+            now: start,
+            frame_time: FRAME,
+        }
+    }
+
+    /// exactly 1/fps per frame, so the same events always make the same frames
+    pub fn fixed(fps: u32) -> Self {
+        let fps = fps.max(1);
+        Clock {
+            fps: Some(fps),
+            frame_time: Duration::from_secs(1) / fps,
+            ..Clock::real_time()
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    /// since the first frame
+    pub fn elapsed(&self) -> Duration {
+        self.now - self.start
+    }
+
+    /// how far the last step went
+    pub fn frame_time(&self) -> Duration {
+        self.frame_time
+    }
+
+    /// how long a frame should take on screen
+    pub fn frame_length(&self) -> Duration {
+        match self.fps {
+            Some(fps) => Duration::from_secs(1) / fps,
+            None => FRAME,
+        }
+    }
+
+    /// on to the next frame
+    pub fn advance(&mut self) {
+        self.frames += 1;
+        let next = match self.fps {
+            // counted from the start so rounding doesn't add up
+            Some(fps) => self.start + Duration::from_secs_f64(self.frames as f64 / fps as f64),
+            None => Instant::now(),
+        };
+        self.frame_time = next - self.now;
+        self.now = next;
+    }
+}
@@ -1,28 +1,35 @@
+use std::time::Instant;
+
 use serde::{Serialize, Deserialize};
 use midi_msg::{MidiMsg, ChannelVoiceMsg, Channel};
 
-use crate::{graphics::*, midi::MidiProcessor};
+use crate::{graphics::*, midi::{self, MidiProcessor, MidiEvent, Subscription}, transport::TimeSpan};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct Kit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
     pub drums: Vec<Drum>,
 }
 impl MidiProcessor for Kit {
-    fn deal_with(&mut self, message: MidiMsg) {
-        match message {
+    fn deal_with(&mut self, event: MidiEvent, now: Instant) {
+        if !event.is_from(&self.input) {
+            return;
+        }
+        match &event.msg {
             MidiMsg::ChannelVoice {channel, msg} => {
-                if channel == Channel::Ch10 {
+                if *channel == Channel::Ch10 {
                     match msg {
                         ChannelVoiceMsg::NoteOn { note, velocity: _ } => {
                             for drum in &mut self.drums {
-                                if drum.note == note {
-                                    drum.state.trigger();
+                                if drum.note == *note && event.is_from(&drum.input) {
+                                    drum.state.trigger(event.ms_ago(now));
                                 }
                             }
                         },
                         ChannelVoiceMsg::NoteOff { note, velocity: _ } => {
                             for drum in &mut self.drums {
-                                if drum.note == note {
+                                if drum.note == *note && event.is_from(&drum.input) {
                                     drum.state.release();
                                 }
                             }
@@ -34,20 +41,46 @@ impl MidiProcessor for Kit {
             _ => {}
         }
     }
+
+    fn subscriptions(&self) -> Vec<Subscription> {
+        // a drum with its own input still has to agree with the kit's
+        self.drums.iter().filter(|drum| {
+            drum.input.is_none() || self.input.is_none() || drum.input == self.input
+        }).map(|drum| Subscription {
+            channel: Some(midi::Channel::Ch10),
+            notes: Some(drum.note..=drum.note),
+            input: drum.input.clone().or(self.input.clone()),
+        }).collect()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Drum {
+    /// keeps its state across reloads even if everything else about it changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// what it was filled in from, see template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// which of setup.included it's from, None if it's in the setup itself
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub from: Option<usize>,
     pub xpos: u32,
     pub ypos: u32,
     pub note: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
     pub gfx: DrumGfx,
-    pub decay_time: u32,
+    pub decay_time: TimeSpan,
     pub style: DrumStyle,
 
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
-    pub state: DrumState
+    pub state: DrumState,
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub tint: Tint,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -77,33 +110,94 @@ pub enum DrumStyle {
 pub struct DrumState {
     pub triggered: bool,
     pub note_held: bool,
-    pub age: u32,
+    /// in ms, counting from when the note actually came in
+    pub age: f32,
+}
+impl Drum {
+    /// what makes it the same drum after a reload
+    pub fn identity(&self) -> String {
+        match &self.id {
+            Some(id) => format!("id {}", id),
+            None => format!("note {} {:?}", self.note, self.input),
+        }
+    }
+
+    pub fn keep_state_from(&mut self, old: Drum) {
+        self.state = old.state;
+        self.tint = old.tint;
+    }
 }
+
 impl DrumState {
-    pub fn trigger(&mut self) {
+    pub fn trigger(&mut self, age: f32) {
         self.triggered = true;
         self.note_held = true;
-        self.age = 0;
+        self.age = age;
     }
     pub fn reset(&mut self) {
         self.triggered = false;
         self.note_held = false;
-        self.age = 0;
+        self.age = 0.0;
     }
 
     pub fn release(&mut self) {
         self.note_held = false;
     }
 
-    pub fn tick_or_reset(&mut self, decay_time: u32) -> bool{
+    /// age it by dt, both in ms
+    pub fn tick_or_reset(&mut self, decay_time: f32, dt: f32) -> bool{
         if self.age >= decay_time && !self.note_held {
             self.reset();
             false
         } else if self.note_held {
             true // don't increase age while held
         } else {
-            self.age += 1;
+            self.age += dt;
             true
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::clock::Clock;
+
+    // how the window ages a drum each frame
+    fn frame(state: &mut DrumState, decay_time: f32, clock: &mut Clock) -> bool {
+        clock.advance();
+        state.tick_or_reset(decay_time, clock.frame_time().as_secs_f32() * 1000.0)
+    }
+
+    #[test]
+    fn fixed_clock_steps_exactly() {
+        let mut clock = Clock::fixed(50);
+        for _ in 0..50 {
+            clock.advance();
+        }
+        assert_eq!(clock.elapsed(), Duration::from_secs(1));
+        assert!(clock.frame_time().abs_diff(Duration::from_millis(20)) < Duration::from_micros(1));
+    }
+
+    #[test]
+    fn decay_fades_out_on_the_same_frame_every_time() {
+        let mut clock = Clock::fixed(50);
+        let mut state = DrumState::default();
+        state.trigger(0.0);
+        // held, it doesn't age
+        for _ in 0..10 {
+            assert!(frame(&mut state, 90.0, &mut clock));
+        }
+        assert_eq!(state.age, 0.0);
+        state.release();
+        // 20ms a frame, past 90ms after 5 of them and gone on the 6th
+        for _ in 0..5 {
+            assert!(frame(&mut state, 90.0, &mut clock));
+        }
+        assert!((state.age - 100.0).abs() < 0.01);
+        assert!(!frame(&mut state, 90.0, &mut clock));
+        assert!(state == DrumState::default());
+    }
+}
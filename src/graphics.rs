@@ -31,6 +31,63 @@ impl RGB {
         RGB { r, g, b }
     }
 
+    /// brighter or darker, unlike dimmed this goes all the way to black
+    pub fn scaled(&self, factor: f32) -> Self {
+        let f = factor.max(0.0);
+        let r = (self.r as f32 * f).min(255.0) as u8;
+        let g = (self.g as f32 * f).min(255.0) as u8;
+        let b = (self.b as f32 * f).min(255.0) as u8;
+        RGB { r, g, b }
+    }
+
+    /// part of the way towards another color, 0.0 is self and 1.0 is other
+    pub fn mixed(&self, other: &RGB, amount: f32) -> Self {
+        let a = amount.clamp(0.0, 1.0);
+        let mix = |x: u8, y: u8| (x as f32 + (y as f32 - x as f32) * a).round() as u8;
+        RGB {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
+        }
+    }
+
+    /// turn the hue around the color wheel by some degrees
+    pub fn hue_shifted(&self, degrees: f32) -> Self {
+        let r = self.r as f32 / 255.0;
+        let g = self.g as f32 / 255.0;
+        let b = self.b as f32 / 255.0;
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        if delta <= f32::EPSILON {
+            return *self; // greys have no hue
+        }
+        let hue = if r >= g && r >= b {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if g >= b {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let hue = (hue + degrees).rem_euclid(360.0);
+
+        // back from hsv, value is max and chroma is delta
+        let x = delta * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+        let m = max - delta;
+        let (r, g, b) = match (hue / 60.0) as u32 {
+            0 => (delta, x, 0.0),
+            1 => (x, delta, 0.0),
+            2 => (0.0, delta, x),
+            3 => (0.0, x, delta),
+            4 => (x, 0.0, delta),
+            _ => (delta, 0.0, x),
+        };
+        RGB::new(
+            ((r + m) * 255.0).round() as u8,
+            ((g + m) * 255.0).round() as u8,
+            ((b + m) * 255.0).round() as u8,
+        )
+    }
+
     pub fn fff() -> Self {
         RGB::new(255,255,255)
     }
@@ -78,6 +135,32 @@ impl<'de> Deserialize<'de> for RGB {
     }
 }
 
+/// color changes made while running (by cc maps), not saved
+#[derive(Clone, Copy)]
+pub struct Tint {
+    pub brightness: f32,
+    /// degrees around the color wheel
+    pub hue: f32,
+}
+impl Default for Tint {
+    fn default() -> Self {
+        Tint { brightness: 1.0, hue: 0.0 }
+    }
+}
+impl Tint {
+    pub fn apply(&self, color: &RGB) -> RGB {
+        let mut c = *color;
+        if self.hue.abs() > 0.5 {
+            c = c.hue_shifted(self.hue);
+        }
+        if (self.brightness - 1.0).abs() > 0.001 {
+            c = c.scaled(self.brightness);
+        }
+        c
+    }
+}
+
+#[derive(Clone)]
 pub struct Bitmap {
     pub path: String,
     pub data: Vec<RGB>,
@@ -87,7 +170,7 @@ pub struct Bitmap {
 impl Bitmap {
     pub fn load_from_file(path: &str) -> Result<Self, Box<dyn Error>> {
         let image = image::open(path)?;
-        let image = image.as_rgb8().expect("failed to load img as rgb8");
+        let image = image.as_rgb8().ok_or(format!("{} isn't an 8 bit rgb image", path))?;
         let xsize = image.width();
         let ysize = image.height();
         let mut data: Vec<RGB> = vec![RGB::default(); (xsize * ysize) as usize];
@@ -102,14 +185,15 @@ impl Bitmap {
             ysize
         })
     }
-    pub fn color(&self, x: u32, y: u32, dimmed: Option<f32>) -> u32 {
+    pub fn color(&self, x: u32, y: u32, dimmed: Option<f32>, tint: &Tint) -> u32 {
         let buf_pos = ((y as usize) * self.xsize as usize) + x as usize;
         if buf_pos >= self.data.len() {
             panic!("meow {} no {} {}", self.data.len(), x, y);
         } else {
+            let c = tint.apply(&self.data[buf_pos]);
             match dimmed {
-                Some(f) => self.data[buf_pos].dimmed(f).irl(),
-                None => self.data[buf_pos].irl()
+                Some(f) => c.dimmed(f).irl(),
+                None => c.irl()
             }
         }
     }
@@ -1,11 +1,23 @@
 use minifb::Key;
-use crate::{chord::Chord, drum::Drum, Setup, midi::Channel};
+use midi_msg::{MidiMsg, ChannelVoiceMsg};
+use crate::{chord::Chord, drum::Drum, text::Text, Setup, midi::{Channel, MidiEvent}};
+use crate::modulation::{CcMap, Curve, Param, ChordParam, DrumParam, TextParam};
 
 
 #[derive(Debug)]
 pub enum Inst {
     Chord,
     Drum,
+    Text,
+}
+
+/// what the next incoming message gets bound to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Learn {
+    /// a drum's note, or a chord's or text's channel
+    Note,
+    /// a cc map moving the focused instrument
+    Cc,
 }
 
 pub enum Direction {
@@ -26,12 +38,32 @@ impl Direction {
     }
 }
 
+/// F1 is the first scene, F2 the next and so on
+pub fn scene_from_key(key: &Key) -> Option<usize> {
+    match key {
+        Key::F1 => Some(0),
+        Key::F2 => Some(1),
+        Key::F3 => Some(2),
+        Key::F4 => Some(3),
+        Key::F5 => Some(4),
+        Key::F6 => Some(5),
+        Key::F7 => Some(6),
+        Key::F8 => Some(7),
+        Key::F9 => Some(8),
+        Key::F10 => Some(9),
+        Key::F11 => Some(10),
+        Key::F12 => Some(11),
+        _ => None
+    }
+}
+
 #[derive(Debug)]
 pub struct KeyboardFocus {
     pub editing: bool,
     pub index: u8,
     pub inst: Inst,
     pub preview_all: bool,
+    pub learning: Option<Learn>,
 }
 impl KeyboardFocus {
     pub fn new() -> Self {
@@ -39,7 +71,8 @@ impl KeyboardFocus {
             editing: false,
             index: 0,
             inst: Inst::Chord,
-            preview_all: false
+            preview_all: false,
+            learning: None,
         }
     }
     pub fn get_focused_chord(&self, chords: &Vec<Chord>) -> Option<usize> {
@@ -57,6 +90,13 @@ impl KeyboardFocus {
             None
         }
     }
+    pub fn get_focused_text(&self, texts: &[Text]) -> Option<usize> {
+        if (self.index as usize) < texts.len() {
+            Some(self.index as usize)
+        } else {
+            None
+        }
+    }
     pub fn adjustment(&self, setup: &mut Setup, direction: Direction, shift: bool, ctrl: bool) {
         if !self.editing {
             return;
@@ -114,6 +154,17 @@ impl KeyboardFocus {
                     }
                 }
             },
+            Inst::Text => {
+                if let Some(i) = self.get_focused_text(&setup.texts) {
+                    let text = &mut setup.texts[i];
+                    match direction {
+                        Direction::Down => text.ypos += amount,
+                        Direction::Up => text.ypos = text.ypos.saturating_sub(amount),
+                        Direction::Left => text.xpos = text.xpos.saturating_sub(amount),
+                        Direction::Right => text.xpos += amount,
+                    }
+                }
+            },
         }
     }
 
@@ -138,7 +189,89 @@ impl KeyboardFocus {
                     setup.kit.drums[f.unwrap()].ypos = y;
                 }
             },
+            Inst::Text => {
+                if let Some(i) = self.get_focused_text(&setup.texts) {
+                    setup.texts[i].xpos = x;
+                    setup.texts[i].ypos = y;
+                }
+            },
         }
     }
+
+    /// while learning, bind the focused instrument to this event if it's the right kind.
+    /// returns what was learned. new cc maps move xpos across the window (0 to width)
+    pub fn learn(&mut self, setup: &mut Setup, event: &MidiEvent, width: u32) -> Option<String> {
+        let learned = match self.learning? {
+            Learn::Note => {
+                let (channel, note) = match &event.msg {
+                    MidiMsg::ChannelVoice { channel, msg: ChannelVoiceMsg::NoteOn { note, velocity } } if *velocity > 0 => {
+                        (Channel::from(*channel as u8), *note)
+                    },
+                    _ => return None,
+                };
+                self.learn_note(setup, channel, note)
+            },
+            Learn::Cc => {
+                let (channel, cc, _value) = event.control_change()?;
+                // name the input only if there's more than one to tell apart
+                let input = if setup.inputs.is_empty() { None } else { Some(event.source.clone()) };
+                self.learn_cc(setup, channel, cc, input, width)
+            },
+        };
+        self.learning = None;
+        Some(learned.unwrap_or_else(|| "nothing focused to learn".to_string()))
+    }
+
+    fn learn_note(&mut self, setup: &mut Setup, channel: Channel, note: u8) -> Option<String> {
+        match self.inst {
+            Inst::Chord => {
+                let i = self.get_focused_chord(&setup.chords)?;
+                setup.chords[i].channel = channel;
+                // chords are focused by channel, follow it
+                self.index = channel as u8;
+                Some(format!("chord now on channel {}", channel as u8 + 1))
+            },
+            Inst::Drum => {
+                let i = self.get_focused_drum(&setup.kit.drums)?;
+                setup.kit.drums[i].note = note;
+                Some(format!("drum {} now on note {}", i + 1, note))
+            },
+            Inst::Text => {
+                let i = self.get_focused_text(&setup.texts)?;
+                setup.texts[i].channel = channel;
+                Some(format!("text {} now on channel {}", i + 1, channel as u8 + 1))
+            },
+        }
+    }
+
+    fn learn_cc(&self, setup: &mut Setup, channel: Channel, cc: u8, input: Option<String>, width: u32) -> Option<String> {
+        let target = match self.inst {
+            Inst::Chord => Param::Chord(self.get_focused_chord(&setup.chords)?, ChordParam::Xpos),
+            Inst::Drum => Param::Drum(self.get_focused_drum(&setup.kit.drums)?, DrumParam::Xpos),
+            Inst::Text => Param::Text(self.get_focused_text(&setup.texts)?, TextParam::Xpos),
+        };
+        // rebind a map that's already on this instrument, or make a new one
+        match setup.cc_maps.iter_mut().find(|map| map.target.same_instrument(&target)) {
+            Some(map) => {
+                map.channel = channel;
+                map.cc = cc;
+                map.input = input;
+                Some(format!("cc {} on channel {} now moves {}", cc, channel as u8 + 1, map.target.name()))
+            },
+            None => {
+                setup.cc_maps.push(CcMap {
+                    channel,
+                    cc,
+                    input,
+                    target,
+                    min: 0.0,
+                    max: width as f32,
+                    curve: Curve::Linear,
+                });
+                Some(format!("cc {} on channel {} now moves {}", cc, channel as u8 + 1, target.name()))
+            },
+        }
+    }
+
     
 }
\ No newline at end of file
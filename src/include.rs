@@ -0,0 +1,156 @@
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::migrate;
+use crate::template::{Kind, pointer};
+use crate::validate::{self, Problem, Problems};
+
+
+/// a setup file as it was read, the setup itself or one it includes
+pub struct SetupFile {
+    /// as it was asked for, for showing and writing to
+    pub path: String,
+    /// the same file however it's reached, to tell if it's been read already
+    pub real_path: PathBuf,
+    pub text: String,
+    /// brought up to date already
+    pub json: Value,
+    /// what it was written as, see migrate
+    pub version: u32,
+}
+impl SetupFile {
+    /// something wrong at a path in here
+    pub fn problem(&self, path: &str, message: String) -> Problem {
+        let mut problem = validate::locate(&self.text, path, message);
+        problem.file = self.path.clone();
+        problem
+    }
+}
+
+/// which file each top level chord, drum and text came from, in Kind::ALL order.
+/// Some(i) is setup.included[i], None the setup itself
+pub type Origins = [Vec<Option<usize>>; 3];
+
+/// read a setup and everything it includes (and they include), checking each one.
+/// the setup comes first, then the rest in the order they're met, each only once.
+/// a file without a version is `unversioned`, unless it's included,
+/// then it's the same as the file including it
+pub fn read_all(path: &str, unversioned: u32) -> Result<Vec<SetupFile>, Problems> {
+    let mut files = vec![];
+    let mut problems = vec![];
+    read(path, unversioned, &mut files, &mut problems);
+    if problems.is_empty() {
+        Ok(files)
+    } else {
+        Err(Problems(problems))
+    }
+}
+
+fn read(path: &str, unversioned: u32, files: &mut Vec<SetupFile>, problems: &mut Vec<Problem>) {
+    // "sub/../a.json" is still a.json
+    let real_path = match fs::canonicalize(path) {
+        Ok(real_path) => real_path,
+        Err(e) => return problems.extend(Problems::file(path, e).0),
+    };
+    if files.iter().any(|file| file.real_path == real_path) {
+        return;
+    }
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return problems.extend(Problems::file(path, e).0),
+    };
+    let found = validate::check(&text);
+    let broken = !found.is_empty();
+    problems.extend(found.into_iter().map(|mut problem| {
+        problem.file = path.to_string();
+        problem
+    }));
+    // if it didn't parse that's just been reported
+    let mut json: Value = serde_json::from_str(&text).unwrap_or(Value::Null);
+    // ms came in before versions did, so there's no telling from the file
+    if !broken && json.get("version").is_none() && unversioned == 1 {
+        println!("{} has no version, taking its times to be in frames (--unversioned 2 if they're in ms already)", path);
+    }
+    let version = if broken {
+        unversioned
+    } else {
+        migrate::upgrade(&mut json, unversioned)
+    };
+    if version < migrate::VERSION && !broken {
+        println!("{} is from version {}, bringing it up to date (saving keeps it that way)", path, version);
+    }
+    // relative to the file they're in
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let includes: Vec<String> = match json.get("include") {
+        Some(Value::Array(list)) => list.iter()
+            .filter_map(Value::as_str)
+            .map(|include| dir.join(include).to_string_lossy().to_string())
+            .collect(),
+        _ => vec![],
+    };
+    files.push(SetupFile { path: path.to_string(), real_path, text, json, version });
+    for include in includes {
+        read(&include, version, files, problems);
+    }
+}
+
+/// put the included files' instruments in with the setup's own, ahead of them
+pub fn merge(mut jsons: Vec<Value>) -> (Value, Origins) {
+    let mut json = jsons.remove(0);
+    let mut from: Origins = Default::default();
+    for (n, kind) in Kind::ALL.iter().enumerate() {
+        let mut all = vec![];
+        for (i, included) in jsons.iter_mut().enumerate() {
+            if let Some(Value::Array(list)) = included.pointer_mut(&pointer(kind.list())) {
+                from[n].extend(list.iter().map(|_| Some(i)));
+                all.append(list);
+            }
+        }
+        if let Some(own) = list_in(&mut json, kind.list()) {
+            from[n].extend(own.iter().map(|_| None));
+            all.append(own);
+            *own = all;
+        }
+    }
+    (json, from)
+}
+
+/// undo merge, take the included files' instruments back out of the setup's json and into theirs
+pub fn split(json: &mut Value, from: &Origins, included: &mut [Value]) {
+    for (n, kind) in Kind::ALL.iter().enumerate() {
+        let own = match list_in(json, kind.list()) {
+            Some(own) => own,
+            None => continue,
+        };
+        let mut theirs: Vec<Vec<Value>> = included.iter().map(|_| vec![]).collect();
+        for (j, item) in mem::take(own).into_iter().enumerate() {
+            match from[n].get(j).copied().flatten() {
+                Some(i) => theirs[i].push(item),
+                None => own.push(item),
+            }
+        }
+        for (file, list) in included.iter_mut().zip(theirs) {
+            // don't add an empty list that wasn't there
+            if list.is_empty() && file.pointer(&pointer(kind.list())).is_none() {
+                continue;
+            }
+            if let Some(slot) = list_in(file, kind.list()) {
+                *slot = list;
+            }
+        }
+    }
+}
+
+/// the list at a path like "kit.drums", made if it's not there yet
+fn list_in<'a>(json: &'a mut Value, path: &str) -> Option<&'a mut Vec<Value>> {
+    let keys: Vec<&str> = path.split('.').collect();
+    let (last, before) = keys.split_last()?;
+    let mut at = json;
+    for key in before {
+        at = at.as_object_mut()?.entry(*key).or_insert_with(|| Value::Object(Map::new()));
+    }
+    at.as_object_mut()?.entry(*last).or_insert_with(|| Value::Array(vec![])).as_array_mut()
+}
@@ -0,0 +1,290 @@
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use midi_msg::{MidiMsg, ReceiverContext};
+use midir::{MidiInput, MidiOutput, Ignore, MidiInputPort, MidiInputConnection};
+#[cfg(unix)]
+use midir::os::unix::VirtualInput;
+use regex::Regex;
+
+use crate::midi::MidiEvent;
+use crate::thru::Thru;
+
+
+/// how often to look for a missing port (or check the current one is still there)
+const RETRY_EVERY: Duration = Duration::from_secs(1);
+
+/// how an input port gets picked out of whatever's plugged in
+#[derive(Clone)]
+pub enum PortSelector {
+    /// part of the port name, or a regex
+    Pattern {
+        text: String,
+        regex: Option<Regex>,
+    },
+    /// position in the port list (breaks whenever alsa reorders clients)
+    Index(usize),
+    /// don't look for one, make our own with this name for other programs to connect to
+    Virtual(String),
+}
+impl PortSelector {
+    pub fn pattern(text: &str) -> Self {
+        PortSelector::Pattern {
+            text: text.to_string(),
+            regex: Regex::new(text).ok(),
+        }
+    }
+
+    pub fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            PortSelector::Pattern { text, regex } => {
+                name.contains(text.as_str())
+                    || regex.as_ref().map(|r| r.is_match(name)).unwrap_or(false)
+            },
+            PortSelector::Index(i) => *i == index,
+            PortSelector::Virtual(_) => false,
+        }
+    }
+}
+impl fmt::Display for PortSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortSelector::Pattern { text, regex: _ } => write!(f, "'{}'", text),
+            PortSelector::Index(i) => write!(f, "#{}", i),
+            PortSelector::Virtual(name) => write!(f, "virtual '{}'", name),
+        }
+    }
+}
+
+pub enum PortStatus {
+    Connected(String),
+    Lost(String),
+    Waiting(String),
+}
+impl fmt::Display for PortStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortStatus::Connected(name) => write!(f, "Connected to {}", name),
+            PortStatus::Lost(name) => write!(f, "Lost {}, retrying..", name),
+            PortStatus::Waiting(what) => write!(f, "Waiting for midi port {}", what),
+        }
+    }
+}
+
+/// a midi input that keeps looking for its port when it goes away
+pub struct InputPort {
+    /// what instruments call this input, every message gets tagged with it
+    pub name: String,
+    selector: PortSelector,
+    tx: Sender<MidiEvent>,
+    /// everything that comes in also goes out here
+    pub thru: Option<Arc<Thru>>,
+    // only used to list ports, connecting eats the MidiInput
+    lister: MidiInput,
+    conn: Option<(String, MidiInputConnection<()>)>,
+    last_try: Option<Instant>,
+    waiting: bool,
+}
+
+impl InputPort {
+    pub fn open(name: &str, selector: PortSelector, tx: Sender<MidiEvent>) -> Result<Self, Box<dyn Error>> {
+        Ok(InputPort {
+            name: name.to_string(),
+            selector,
+            tx,
+            thru: None,
+            lister: MidiInput::new("grooveanim port lister")?,
+            conn: None,
+            last_try: None,
+            waiting: false,
+        })
+    }
+
+    /// ask on the console when the setup doesn't say which port to use
+    pub fn pick(name: &str, tx: Sender<MidiEvent>) -> Result<Self, Box<dyn Error>> {
+        let lister = MidiInput::new("grooveanim port lister")?;
+        let in_port = get_in_port(&lister)?;
+        let port_name = without_numbers(&lister.port_name(&in_port)?);
+        Self::open(name, PortSelector::pattern(&port_name), tx)
+    }
+
+    /// (re)connect if needed. call this every frame, it only does work every so often
+    pub fn poll(&mut self) -> Option<PortStatus> {
+        if let Some(t) = self.last_try {
+            if t.elapsed() < RETRY_EVERY {
+                return None;
+            }
+        }
+        self.last_try = Some(Instant::now());
+
+        if let PortSelector::Virtual(port_name) = &self.selector {
+            // ours, so it can't go anywhere
+            if self.conn.is_some() || self.waiting {
+                return None;
+            }
+            return match self.create_virtual(port_name) {
+                Ok(conn) => {
+                    self.conn = Some((port_name.clone(), conn));
+                    Some(PortStatus::Connected(format!("virtual port '{}'", port_name)))
+                },
+                Err(e) => {
+                    self.waiting = true;
+                    Some(PortStatus::Waiting(format!("{} ({})", self.selector, e)))
+                },
+            };
+        }
+
+        let found = self.find();
+        if let Some((connected_name, _)) = &self.conn {
+            if found.as_ref().map(|(_, name)| name == connected_name).unwrap_or(false) {
+                return None; // still there
+            }
+            // gone (or something else took its place in the list)
+            let (name, conn) = self.conn.take().unwrap();
+            conn.close();
+            self.waiting = true;
+            return Some(PortStatus::Lost(name));
+        }
+
+        let status = match found {
+            Some((port, name)) => match self.connect(&port) {
+                Ok(conn) => {
+                    self.conn = Some((name.clone(), conn));
+                    self.waiting = false;
+                    return Some(PortStatus::Connected(name));
+                },
+                Err(e) => PortStatus::Waiting(format!("{} ({})", name, e)),
+            },
+            None => PortStatus::Waiting(self.selector.to_string()),
+        };
+        if self.waiting {
+            None // already said so
+        } else {
+            self.waiting = true;
+            Some(status)
+        }
+    }
+
+    fn find(&self) -> Option<(MidiInputPort, String)> {
+        for (i, port) in self.lister.ports().iter().enumerate() {
+            if let Ok(name) = self.lister.port_name(port) {
+                if self.selector.matches(i, &name) {
+                    return Some((port.clone(), name));
+                }
+            }
+        }
+        None
+    }
+
+    fn connect(&self, port: &MidiInputPort) -> Result<MidiInputConnection<()>, Box<dyn Error>> {
+        let mut midi_in = MidiInput::new("midir reading input")?;
+        midi_in.ignore(Ignore::None);
+        let conn = midi_in.connect(port, "midir-read-input", self.callback(), ())?;
+        Ok(conn)
+    }
+
+    #[cfg(unix)]
+    fn create_virtual(&self, port_name: &str) -> Result<MidiInputConnection<()>, Box<dyn Error>> {
+        let mut midi_in = MidiInput::new("grooveanim")?;
+        midi_in.ignore(Ignore::None);
+        let conn = midi_in.create_virtual(port_name, self.callback(), ())?;
+        Ok(conn)
+    }
+
+    #[cfg(not(unix))]
+    fn create_virtual(&self, _port_name: &str) -> Result<MidiInputConnection<()>, Box<dyn Error>> {
+        Err("virtual ports aren't supported on this platform".into())
+    }
+
+    /// turns raw midi into tagged events for the main loop
+    fn callback(&self) -> impl FnMut(u64, &[u8], &mut ()) + Send + 'static {
+        let tx = self.tx.clone();
+        let source = self.name.clone();
+        let thru = self.thru.clone();
+        let mut ctx = ReceiverContext::new();
+        // midir stamps are microseconds from some point of the backend's choosing,
+        // pin the first one to the clock and count from there
+        let mut anchor: Option<(u64, Instant)> = None;
+        move |stamp, message, _| {
+            if let Some(thru) = &thru {
+                thru.forward(message);
+            }
+            let now = Instant::now();
+            let time = match anchor {
+                Some((first_stamp, first_time)) => {
+                    first_time + Duration::from_micros(stamp.saturating_sub(first_stamp))
+                },
+                None => now,
+            };
+            // never in the future, re-pin if the clocks have drifted that way
+            let time = if anchor.is_none() || time > now {
+                anchor = Some((stamp, now));
+                now
+            } else {
+                time
+            };
+            let (msg, _len) = MidiMsg::from_midi_with_context(&message, &mut ctx).expect("Not an error");
+            tx.send(MidiEvent { source: source.clone(), time, msg }).expect("failed to tx message");
+        }
+    }
+}
+
+/// Get an input port (read from console if multiple are available)
+fn get_in_port(midi_in: &MidiInput) -> Result<MidiInputPort, Box<dyn Error>> {
+    let in_ports = midi_in.ports();
+    //in_ports = vec![in_ports.remove(0)];
+
+    let in_port = match in_ports.len() {
+        0 => return Err("no input port found".into()),
+        1 => {
+            println!("Choosing the only available input port: {}", midi_in.port_name(&in_ports[0]).unwrap());
+            &in_ports[0]
+        },
+        _ => {
+            println!("\nAvailable input ports:");
+            for (i, p) in in_ports.iter().enumerate() {
+                println!("{}: {}", i, midi_in.port_name(p).unwrap());
+            }
+            print!("Please select input port: ");
+            std::io::stdout().flush()?;
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input)?;
+            let i = input.trim().parse::<usize>()?;
+            in_ports.get(i).ok_or("no such port")?
+        }
+    };
+    Ok(in_port.clone())
+}
+
+/// what the input port at this place in the list is called, see without_numbers
+pub fn port_name(index: usize) -> Result<String, Box<dyn Error>> {
+    let midi_in = MidiInput::new("grooveanim port lister")?;
+    let port = midi_in.ports().get(index).cloned().ok_or(format!("there's no port #{}", index))?;
+    Ok(without_numbers(&midi_in.port_name(&port)?))
+}
+
+/// a port name without the client:port numbers alsa puts on the end,
+/// they change when it's plugged in again
+fn without_numbers(name: &str) -> String {
+    let numbers = Regex::new(r"\s+\d+:\d+$").unwrap();
+    numbers.replace(name, "").to_string()
+}
+
+/// everything plugged in, numbered the way midi_port_id and --port count them
+pub fn list_ports() -> Result<(), Box<dyn Error>> {
+    let midi_in = MidiInput::new("grooveanim port lister")?;
+    println!("Input ports:");
+    for (i, p) in midi_in.ports().iter().enumerate() {
+        println!("{}: {}", i, midi_in.port_name(p)?);
+    }
+    let midi_out = MidiOutput::new("grooveanim port lister")?;
+    println!("Output ports (for thru):");
+    for (i, p) in midi_out.ports().iter().enumerate() {
+        println!("{}: {}", i, midi_out.port_name(p)?);
+    }
+    Ok(())
+}
@@ -1,12 +1,12 @@
 
-use midi_msg::{MidiMsg, ReceiverContext, SystemRealTimeMsg};
-use midir::{MidiInput, Ignore, MidiInputPort};
-use minifb::{Key, MouseButton, MouseMode};
+use clap::Parser;
+use minifb::Key;
 
-use std::io::Write;
 use std::error::Error;
+use std::mem;
 use std::sync::mpsc::{Sender, Receiver};
 use std::sync::mpsc;
+use std::time::Duration;
 
 
 mod chord;
@@ -14,7 +14,7 @@ mod drum;
 mod text;
 
 mod midi;
-use crate::midi::MidiProcessor;
+use crate::midi::{MidiProcessor, MidiEvent};
 
 mod win;
 use crate::win::*;
@@ -23,79 +23,208 @@ mod graphics;
 use crate::graphics::RGB;
 
 mod gui;
-use crate::gui::{KeyboardFocus, Inst, Direction};
+use crate::gui::{KeyboardFocus, Inst, Direction, Learn, scene_from_key};
 
 mod setup;
 use crate::setup::Setup;
 
+mod midifile;
+use crate::midifile::MidiFile;
+
+mod input;
+use crate::input::{InputPort, PortStatus};
+
+mod transport;
+
+mod thru;
+use crate::thru::Thru;
+
+mod router;
+use crate::router::Router;
+
+mod modulation;
+
+mod osc;
+use crate::osc::OscAction;
+
+mod clock;
+use crate::clock::Clock;
+
+mod validate;
+
+mod include;
+mod template;
+mod migrate;
+
+mod watch;
+use crate::watch::{Watcher, Change};
+
+mod cli;
+use crate::cli::{Cli, Mode};
+
+
+/// keep rendering this long after the last event so things can fade out
+const RENDER_TAIL: Duration = Duration::from_secs(2);
+
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut setup = Setup::load("weirdgrv.json")?;
-
-    let mut midi_in = MidiInput::new("midir reading input").unwrap();
-    midi_in.ignore(Ignore::None);
-
-    //let in_port = get_in_port(&midi_in).unwrap();
-    let in_port = &midi_in.ports()[setup.midi_port_id];
-    let in_port_name = midi_in.port_name(&in_port).unwrap();
-    println!("Connection open, reading input from '{}' ...", in_port_name);
-
-    let (tx, rx): (Sender<MidiMsg>, Receiver<MidiMsg>) = mpsc::channel();
-
-    let mut ctx = ReceiverContext::new();
-    // _conn_in needs to be a named parameter, because it needs to be kept alive until the end of the scope
-    let _conn_in = midi_in.connect(&in_port, "midir-read-input", move |_stamp, message, _| {
-        let thread_tx = tx.clone();
-        let (msg, _len) = MidiMsg::from_midi_with_context(&message, &mut ctx).expect("Not an error");
-        if let MidiMsg::SystemRealTime{ msg: SystemRealTimeMsg::TimingClock } = msg {
-            // no-op
-        } else {
-            thread_tx.send(msg).expect("failed to tx message");
-        }
-        
-    }, ())?;
+    let cli = Cli::parse();
+    if cli.list_ports {
+        return input::list_ports();
+    }
+    let mut setup = Setup::load(&cli.setup, cli.unversioned)?;
+    cli.apply(&mut setup);
 
-    let mut win = Win::init(640, 480);
+    match &cli.mode {
+        Some(Mode::Render { file, out }) => render(setup, MidiFile::load(file)?, out, &cli),
+        Some(Mode::Play { file }) => run(setup, Some(MidiFile::load(file)?), &cli),
+        Some(Mode::Live) => run(setup, None, &cli),
+        None => match &cli.midi_file {
+            Some(file) => run(setup, Some(MidiFile::load(file)?), &cli),
+            None => run(setup, None, &cli),
+        },
+    }
+}
 
-    let mut focus = KeyboardFocus::new();
+/// with a window, listening to midi ports (or playing a file)
+fn run(mut setup: Setup, midi_file: Option<MidiFile>, cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let (tx, rx): (Sender<MidiEvent>, Receiver<MidiEvent>) = mpsc::channel();
 
-    while win.window.is_open() && win.running {
-        win.clear();
-        for msg in rx.try_iter() {
-            for chord in &mut setup.chords {
-                chord.deal_with(msg.clone());
+    let mut in_ports: Vec<InputPort> = vec![];
+    match &midi_file {
+        Some(file) => {
+            println!("Playing '{}' ({} events, {:.1}s) ...", file.path, file.events.len(), file.length().as_secs_f32());
+        },
+        None => {
+            let thru = match &setup.thru {
+                Some(thru_setup) => match Thru::open(thru_setup) {
+                    Ok(thru) => {
+                        println!("Passing midi through to '{}'", thru.port_name);
+                        Some(thru)
+                    },
+                    Err(e) => {
+                        println!("can't open thru port.. {}", e);
+                        None
+                    }
+                },
+                None => None,
+            };
+            if setup.inputs.is_empty() {
+                in_ports.push(match setup.port_selector() {
+                    Some(selector) => InputPort::open("default", selector, tx)?,
+                    None => InputPort::pick("default", tx)?,
+                });
+            } else {
+                for input in &setup.inputs {
+                    in_ports.push(InputPort::open(&input.name, input.selector(), tx.clone())?);
+                }
             }
-            setup.kit.deal_with(msg.clone());
-            for text in &mut setup.texts {
-                text.deal_with(msg.clone())
+            for port in &mut in_ports {
+                port.thru = thru.clone();
             }
         }
+    };
 
-        for chord in &mut setup.chords {
-            win.draw_chord(chord, setup.global_transpose);
-        }
-        for drum in &mut setup.kit.drums {
-            win.draw_drum(drum);
+    let clock = match cli.fps {
+        Some(fps) => Clock::fixed(fps),
+        None => Clock::real_time(),
+    };
+    let mut win = Win::init(cli.width, cli.height, cli.scale, clock);
+    win.transport.input = setup.clock_input.clone();
+    win.transport.fixed_bpm = setup.bpm;
+
+    // _osc needs to be a named parameter, because it needs to be kept alive until the end of the scope
+    let (osc_tx, osc_rx) = mpsc::channel();
+    let _osc = match setup.osc_port {
+        Some(port) => match osc::listen(port, osc_tx) {
+            Ok(handle) => {
+                println!("Listening for osc on port {}", port);
+                Some(handle)
+            },
+            Err(e) => {
+                println!("can't listen for osc.. {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut focus = KeyboardFocus::new();
+    let mut pending: Vec<MidiEvent> = vec![];
+    if let Some(file) = midi_file {
+        pending.extend(file.schedule("file", win.clock.now()));
+    }
+    let mut router = Router::build(&setup);
+    let mut watcher = Watcher::new(setup.files(), setup.bitmap_paths());
+
+    while win.is_open() && win.running {
+        win.clear();
+        match watcher.poll() {
+            Some(Change::Setup) => reload(&mut setup, &mut router, &mut win, &mut watcher, cli),
+            Some(Change::Bitmaps(paths)) => {
+                for path in paths {
+                    match setup.reload_bitmap(&path) {
+                        Ok(_) => win.set_status_message(&format!("Reloaded image: {}", path), RGB::fff()),
+                        Err(e) => {
+                            // keep the old one, it's probably still being saved so try again in a bit
+                            watcher.retry(&path);
+                            win.set_status_message(&format!("can't reload {}", path), RGB::color("red"));
+                            println!("error loading image {}.. {}", path, e);
+                        },
+                    }
+                }
+            },
+            None => {},
         }
-        for text in &mut setup.texts {
-            win.draw_text(text);
+        for port in &mut in_ports {
+            if let Some(status) = port.poll() {
+                let message = format!("{}: {}", port.name, status);
+                println!("{}", message);
+                let color = match status {
+                    PortStatus::Connected(_) => RGB::fff(),
+                    _ => RGB::color("red"),
+                };
+                win.set_status_message(&message, color);
+            }
         }
-        if focus.editing {
-            win.draw_editor(&setup, &focus);
+        pending.extend(rx.try_iter());
+        apply_due(&mut pending, &mut setup, &mut router, &mut win, &mut focus);
+        let now = win.clock.now();
+        for msg in osc_rx.try_iter() {
+            match osc::translate(&setup, &msg, now) {
+                Ok(OscAction::Events(target, events)) => {
+                    for event in events {
+                        Router::deliver(&mut setup, target, event, now);
+                    }
+                },
+                Ok(OscAction::Scene(scene)) => switch_scene(&mut setup, &mut router, &mut win, scene),
+                Ok(OscAction::Set(param, value)) => param.set(&mut setup, value),
+                Err(e) => println!("osc: {}", e),
+            }
         }
-        win.draw_status_message();
+
+        draw(&mut win, &mut setup, &focus);
         
         // keyboard time
-        win.window.get_keys_pressed(minifb::KeyRepeat::Yes).iter().for_each(|key|
+        win.keys_pressed().iter().for_each(|key|
             match key {
-                Key::Enter => focus.editing = !focus.editing,
+                Key::Enter => {
+                    focus.editing = !focus.editing;
+                    focus.learning = None;
+                },
                 Key::A => focus.preview_all = !focus.preview_all,
                 Key::C => focus.inst = Inst::Chord,
                 Key::D => focus.inst = Inst::Drum,
+                Key::T => focus.inst = Inst::Text,
+                Key::L | Key::M if focus.editing => {
+                    let learn = if *key == Key::L {Learn::Note} else {Learn::Cc};
+                    // press again to stop waiting
+                    focus.learning = if focus.learning == Some(learn) {None} else {Some(learn)};
+                },
                 Key::Key1 | Key::Key2 | Key::Key3 | Key::Key4 | Key::Key5
                 | Key::Key6 | Key::Key7 | Key::Key8 | Key::Key9 | Key::Key0 => {
                     let val = if *key == Key::Key0 {10} else {*key as u8};
-                    focus.index = if win.window.is_key_down(Key::LeftShift) {
+                    focus.index = if win.key_down(Key::LeftShift) {
                         val - 1 + 10
                     } else {
                         val - 1
@@ -109,29 +238,18 @@ fn main() -> Result<(), Box<dyn Error>> {
                     focus.adjustment(
                         &mut setup, 
                         Direction::from_key(key).unwrap(), 
-                        win.window.is_key_down(Key::LeftShift),
-                        win.window.is_key_down(Key::LeftCtrl),
+                        win.key_down(Key::LeftShift),
+                        win.key_down(Key::LeftCtrl),
                     );
                 }
 
-                Key::R => {
-                    // reload setup
-                    let new_setup = Setup::load(&setup.path);
-                    match new_setup {
-                        Ok(s) => {
-                            win.set_status_message(&format!("Reloaded setup: {}", &setup.path), RGB::fff());
-                            setup = s;
-                        },
-                        Err(e) => {
-                            win.set_status_message("err see console", RGB::color("red"));
-                            println!("error parsing setup.. {}", e);
-                        }
-                    }
-                }
+                Key::R => reload(&mut setup, &mut router, &mut win, &mut watcher, cli),
                 Key::S => {
                     match setup.maybe_write() {
                         Ok(data_length) => {
                             win.set_status_message(&format!("Saved setup: {} ({}c)", &setup.path, data_length), RGB::fff());
+                            // don't reload what we just wrote
+                            watcher = Watcher::new(setup.files(), setup.bitmap_paths());
                         },
                         Err(e) => {
                             win.set_status_message("err see console", RGB::color("red"));
@@ -139,6 +257,11 @@ fn main() -> Result<(), Box<dyn Error>> {
                         },
                     }
                 }
+                Key::F1 | Key::F2 | Key::F3 | Key::F4 | Key::F5 | Key::F6
+                | Key::F7 | Key::F8 | Key::F9 | Key::F10 | Key::F11 | Key::F12 => {
+                    switch_scene(&mut setup, &mut router, &mut win, scene_from_key(key).unwrap());
+                }
+                Key::Escape => win.problems.clear(),
                 Key::Q => {
                     win.running = false;
                 },
@@ -147,8 +270,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         );
 
         // mouse support
-        if win.window.get_mouse_down(MouseButton::Left) {
-            let pos = win.window.get_mouse_pos(MouseMode::Clamp).unwrap();
+        if let Some(pos) = win.mouse_dragging() {
             focus.mouse_adjustment(
                 &mut setup,
                 pos.0,
@@ -163,29 +285,99 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-/// Get an input port (read from console if multiple are available)
-fn get_in_port(midi_in: &MidiInput) -> Result<MidiInputPort, Box<dyn Error>> {    
-    let in_ports = midi_in.ports();
-    //in_ports = vec![in_ports.remove(0)];
+/// no window, fixed steps as fast as it'll go, every frame saved as a png
+fn render(mut setup: Setup, midi_file: MidiFile, out: &str, cli: &Cli) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(out)?;
+    let mut win = Win::headless(cli.width, cli.height, Clock::fixed(cli.fps.unwrap_or(60)));
+    win.transport.input = setup.clock_input.clone();
+    win.transport.fixed_bpm = setup.bpm;
+
+    println!("Rendering '{}' ({:.1}s) to {} ...", midi_file.path, midi_file.length().as_secs_f32(), out);
+    let start = win.clock.now();
+    let end = start + midi_file.length() + RENDER_TAIL;
+    let mut pending = midi_file.schedule("file", start);
+    let mut router = Router::build(&setup);
+    let mut focus = KeyboardFocus::new();
+    let mut frame = 0;
+    while win.clock.now() <= end {
+        win.clear();
+        apply_due(&mut pending, &mut setup, &mut router, &mut win, &mut focus);
+        draw(&mut win, &mut setup, &focus);
+        win.save_frame(&format!("{}/{:06}.png", out, frame))?;
+        win.update();
+        frame += 1;
+    }
+    println!("Wrote {} frames", frame);
+    Ok(())
+}
+
+/// hand out everything that's due by the clock in the order it actually happened,
+/// instruments use event.time so notes in the same frame still start apart
+fn apply_due(pending: &mut Vec<MidiEvent>, setup: &mut Setup, router: &mut Router, win: &mut Win, focus: &mut KeyboardFocus) {
+    pending.sort_by_key(|e| e.time);
+    let now = win.clock.now();
+    let due = pending.iter().take_while(|e| e.time <= now).count();
+    for event in pending.drain(..due) {
+        if let Some(learned) = focus.learn(setup, &event, win.width) {
+            *router = Router::build(setup);
+            win.set_status_message(&format!("Learned: {}", learned), RGB::fff());
+        }
+        if let Some(scene) = setup.scene_change(&event) {
+            switch_scene(setup, router, win, scene);
+        }
+        win.transport.deal_with(event.clone(), now);
+        modulation::apply_cc_maps(setup, &event);
+        router.dispatch(setup, event, now);
+    }
+}
+
+fn draw(win: &mut Win, setup: &mut Setup, focus: &KeyboardFocus) {
+    for chord in &mut setup.chords {
+        win.draw_chord(chord, setup.global_transpose);
+    }
+    for drum in &mut setup.kit.drums {
+        win.draw_drum(drum);
+    }
+    for text in &mut setup.texts {
+        win.draw_text(text);
+    }
+    if focus.editing {
+        win.draw_editor(setup, focus);
+    }
+    if !win.problems.is_empty() {
+        win.draw_problems();
+    }
+    win.draw_status_message();
+}
 
-    let in_port = match in_ports.len() {
-        0 => return Err("no input port found".into()),
-        1 => {
-            println!("Choosing the only available input port: {}", midi_in.port_name(&in_ports[0]).unwrap());
-            &in_ports[0]
+/// load the setup again, keeping the old one if the new one's got problems.
+/// the watcher starts watching whatever the new one uses
+fn reload(setup: &mut Setup, router: &mut Router, win: &mut Win, watcher: &mut Watcher, cli: &Cli) {
+    match Setup::load(&setup.path, cli.unversioned) {
+        Ok(mut new_setup) => {
+            win.set_status_message(&format!("Reloaded setup: {}", &setup.path), RGB::fff());
+            win.problems.clear();
+            cli.apply(&mut new_setup);
+            let old = mem::replace(setup, new_setup);
+            setup.keep_state_from(old);
+            *router = Router::build(setup);
+            win.transport.input = setup.clock_input.clone();
+            win.transport.fixed_bpm = setup.bpm;
+            *watcher = Watcher::new(setup.files(), setup.bitmap_paths());
         },
-        _ => {
-            println!("\nAvailable input ports:");
-            for (i, p) in in_ports.iter().enumerate() {
-                println!("{}: {}", i, midi_in.port_name(p).unwrap());
-            }
-            print!("Please select input port: ");
-            std::io::stdout().flush()?;
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input)?;
-            let i = input.trim().parse::<usize>()?;
-            in_ports.get(i).unwrap()
+        Err(problems) => {
+            win.set_status_message(&format!("Setup has {} problem(s), Esc to hide", problems.0.len()), RGB::color("red"));
+            println!("error parsing setup..\n{}", problems);
+            win.problems = problems.0.iter().map(|p| p.to_string()).collect();
         }
-    };
-    Ok(in_port.clone())
-}
\ No newline at end of file
+    }
+}
+
+fn switch_scene(setup: &mut Setup, router: &mut Router, win: &mut Win, scene: usize) {
+    if setup.switch_scene(scene) {
+        *router = Router::build(setup);
+        win.set_status_message(&format!("Scene {}: {}", scene + 1, setup.name_of_scene(scene)), RGB::fff());
+    } else {
+        win.set_status_message(&format!("No scene {}", scene + 1), RGB::color("red"));
+    }
+}
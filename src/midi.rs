@@ -1,12 +1,29 @@
 use serde::{Serialize, Deserialize, Deserializer, Serializer};
-use midi_msg::MidiMsg;
+use midi_msg::{MidiMsg, ChannelVoiceMsg};
+use midi_msg::Channel as MsgChannel;
+use std::ops::RangeInclusive;
+use std::time::Instant;
 
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Note {
     pub pitch: u8,
     pub velocity: u8,
-    pub age: u32
+    /// aftertouch, changes while the note's held
+    pub pressure: u8,
+    /// what it came in on, chords can take notes from more than one
+    pub channel: Channel,
+    /// in ms, counting from when the note actually came in
+    pub age: f32,
+    /// key's up, but a pedal might still be keeping it
+    pub released: bool,
+    /// was down when the sostenuto pedal went down
+    pub sostenuto: bool,
+}
+impl Note {
+    pub fn sounding(&self, sustain: bool, sostenuto: bool) -> bool {
+        !self.released || sustain || (sostenuto && self.sostenuto)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -52,6 +69,16 @@ impl std::convert::From<u8> for Channel {
     }
 }
 
+impl Channel {
+    /// counting from 1 like everyone does
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.trim().parse::<u8>() {
+            Ok(n @ 1..=16) => Ok(Channel::from(n - 1)),
+            _ => Err(format!("channel should be 1 to 16, not '{}'", s)),
+        }
+    }
+}
+
 impl Serialize for Channel {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -68,12 +95,114 @@ impl<'de> Deserialize<'de> for Channel {
         D: Deserializer<'de>,
     {
         let s: &str = Deserialize::deserialize(deserializer)?;
-        let channel_no = s.parse::<u8>().unwrap();
-        Ok(Channel::from(channel_no - 1))
+        Channel::parse(s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// a message, the name of the input it came in on and when
+#[derive(Clone)]
+pub struct MidiEvent {
+    pub source: String,
+    pub time: Instant,
+    pub msg: MidiMsg,
+}
+impl MidiEvent {
+    /// a note on, velocity 0 makes it a note off
+    pub fn note(source: &str, time: Instant, channel: Channel, note: u8, velocity: u8) -> Self {
+        let msg = if velocity == 0 {
+            ChannelVoiceMsg::NoteOff { note, velocity: 0 }
+        } else {
+            ChannelVoiceMsg::NoteOn { note, velocity }
+        };
+        MidiEvent {
+            source: source.to_string(),
+            time,
+            msg: MidiMsg::ChannelVoice { channel: MsgChannel::from_u8(channel as u8), msg },
+        }
+    }
+
+    /// channel, control number and value of a control change.
+    /// goes back to the raw bytes so it doesn't matter which variant midi-msg parsed it into
+    pub fn control_change(&self) -> Option<(Channel, u8, u8)> {
+        if let MidiMsg::ChannelVoice { channel, msg: ChannelVoiceMsg::ControlChange { .. } } = &self.msg {
+            let bytes = self.msg.to_midi();
+            if bytes.len() >= 3 {
+                return Some((Channel::from(*channel as u8), bytes[1], bytes[2]));
+            }
+        }
+        None
+    }
+
+    /// how long before now this happened, in ms
+    pub fn ms_ago(&self, now: Instant) -> f32 {
+        now.saturating_duration_since(self.time).as_secs_f32() * 1000.0
+    }
+
+    /// instruments without an input listen to everything
+    pub fn is_from(&self, input: &Option<String>) -> bool {
+        match input {
+            Some(name) => *name == self.source,
+            None => true,
+        }
+    }
+}
+
+/// a kind of message a processor wants, the router only passes on what matches
+#[derive(Clone, Debug)]
+pub struct Subscription {
+    /// None hears every channel, and system messages too
+    pub channel: Option<Channel>,
+    /// only for messages about a note, others on the channel always match
+    pub notes: Option<RangeInclusive<u8>>,
+    pub input: Option<String>,
+}
+impl Subscription {
+    pub fn everything() -> Self {
+        Subscription { channel: None, notes: None, input: None }
+    }
+
+    pub fn channel(channel: Channel, input: &Option<String>) -> Self {
+        Subscription { channel: Some(channel), notes: None, input: input.clone() }
+    }
+
+    pub fn matches(&self, event: &MidiEvent) -> bool {
+        if !event.is_from(&self.input) {
+            return false;
+        }
+        let wanted = match self.channel {
+            Some(c) => c,
+            None => return true,
+        };
+        match &event.msg {
+            MidiMsg::ChannelVoice { channel, msg } => {
+                if *channel as u8 != wanted as u8 {
+                    return false;
+                }
+                let note = match msg {
+                    ChannelVoiceMsg::NoteOn { note, .. }
+                    | ChannelVoiceMsg::NoteOff { note, .. }
+                    | ChannelVoiceMsg::PolyPressure { note, .. } => *note,
+                    _ => return true,
+                };
+                match &self.notes {
+                    Some(range) => range.contains(&note),
+                    None => true,
+                }
+            },
+            // all notes off and the like go to everyone, whatever channel they're on
+            MidiMsg::ChannelMode { .. } => true,
+            _ => false,
+        }
     }
 }
 
 pub trait MidiProcessor {
-    fn deal_with(&mut self, message: MidiMsg);
+    /// now is the clock's, for working out how long ago the event happened
+    fn deal_with(&mut self, event: MidiEvent, now: Instant);
+
+    /// what this wants to hear, by default everything
+    fn subscriptions(&self) -> Vec<Subscription> {
+        vec![Subscription::everything()]
+    }
 }
 
@@ -0,0 +1,103 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use midi_msg::{MidiMsg, ChannelVoiceMsg, ReceiverContext};
+use midly::{Smf, Format, Timing, TrackEventKind, MetaMessage};
+
+use crate::midi::MidiEvent;
+
+
+/// a message and when it should happen, counted from the start of the song
+pub struct TimedMsg {
+    pub time: Duration,
+    pub msg: MidiMsg,
+}
+
+/// a standard midi file flattened into one list of messages in playing order
+pub struct MidiFile {
+    pub path: String,
+    pub events: Vec<TimedMsg>,
+}
+
+impl MidiFile {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let data = std::fs::read(path)?;
+        let smf = Smf::parse(&data)?;
+        if smf.header.format == Format::Sequential {
+            return Err("format 2 (sequential) midi files aren't supported".into());
+        }
+
+        // merge every track into one list of (absolute tick, event)
+        // tracks stay in file order for events on the same tick (sort is stable)
+        let mut merged = vec![];
+        for track in &smf.tracks {
+            let mut tick: u64 = 0;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                merged.push((tick, event.kind));
+            }
+        }
+        merged.sort_by_key(|(tick, _)| *tick);
+
+        // follow the tempo map to turn ticks into wall-clock time
+        let mut events = vec![];
+        let mut ctx = ReceiverContext::new();
+        let mut tempo: u64 = 500_000; // microseconds per beat, 120bpm until told otherwise
+        let mut last_tick: u64 = 0;
+        let mut micros: f64 = 0.0;
+        for (tick, kind) in merged {
+            micros += match smf.header.timing {
+                Timing::Metrical(tpb) => {
+                    (tick - last_tick) as f64 * tempo as f64 / tpb.as_int() as f64
+                },
+                Timing::Timecode(fps, subframes) => {
+                    (tick - last_tick) as f64 * 1_000_000.0 / (fps.as_f32() as f64 * subframes as f64)
+                },
+            };
+            last_tick = tick;
+
+            match kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(t)) => {
+                    tempo = t.as_int() as u64;
+                },
+                _ => {
+                    let live = match kind.as_live_event() {
+                        Some(l) => l,
+                        None => continue, // other meta events
+                    };
+                    let mut buf = Vec::new();
+                    live.write_std(&mut buf)?;
+                    if let Ok((mut msg, _len)) = MidiMsg::from_midi_with_context(&buf, &mut ctx) {
+                        // files mostly say note off as a note on with velocity 0
+                        if let MidiMsg::ChannelVoice { channel, msg: ChannelVoiceMsg::NoteOn { note, velocity: 0 } } = msg {
+                            msg = MidiMsg::ChannelVoice { channel, msg: ChannelVoiceMsg::NoteOff { note, velocity: 0 } };
+                        }
+                        events.push(TimedMsg {
+                            time: Duration::from_micros(micros as u64),
+                            msg,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(MidiFile {
+            path: path.to_string(),
+            events,
+        })
+    }
+
+    pub fn length(&self) -> Duration {
+        self.events.last().map(|e| e.time).unwrap_or_default()
+    }
+
+    /// every message as an event stamped with when it's due, the song starting at start.
+    /// they wait in the main loop's queue until the clock gets there
+    pub fn schedule(self, source: &str, start: Instant) -> Vec<MidiEvent> {
+        self.events.into_iter().map(|event| MidiEvent {
+            source: source.to_string(),
+            time: start + event.time,
+            msg: event.msg,
+        }).collect()
+    }
+}
@@ -0,0 +1,113 @@
+use std::error::Error;
+use std::fs;
+
+use serde_json::{Map, Value};
+
+use crate::input;
+use crate::template::{self, Kind};
+
+
+/// the version setups are written as now
+pub const VERSION: u32 = 3;
+
+/// STEPS[n] takes a setup's json from version n+1 to n+2,
+/// false if it can't yet (it's tried again next time the file's read)
+const STEPS: [fn(&mut Value) -> bool; VERSION as usize - 1] = [frames_to_ms, name_the_port];
+
+/// bring a setup file's json as up to date as it'll go, gives back the version it was.
+/// one without a version is taken to be `unversioned` (already checked by validate)
+pub fn upgrade(json: &mut Value, unversioned: u32) -> u32 {
+    let version = json.get("version")
+        .and_then(Value::as_u64)
+        .map(|v| v as u32)
+        .unwrap_or(unversioned)
+        .clamp(1, VERSION);
+    let mut now = version;
+    for step in &STEPS[version as usize - 1..] {
+        if !step(json) {
+            break;
+        }
+        now += 1;
+    }
+    // up the top where it's easy to see
+    if let Value::Object(map) = json {
+        let mut upgraded = Map::new();
+        upgraded.insert("version".to_string(), Value::from(now));
+        upgraded.extend(std::mem::take(map).into_iter().filter(|(key, _)| key != "version"));
+        *map = upgraded;
+    }
+    version
+}
+
+/// the version an upgraded json is at now
+pub fn version_of(json: &Value) -> u32 {
+    json.get("version").and_then(Value::as_u64).map_or(VERSION, |v| v as u32)
+}
+
+/// keep a file from an older version before it's written over with a newer one
+pub fn backup(path: &str, version: u32, new_version: u32) -> Result<(), Box<dyn Error>> {
+    if version < new_version {
+        let to = format!("{}.v{}.bak", path, version);
+        fs::copy(path, &to)?;
+        println!("Kept version {} of {} as {}", version, path, to);
+    }
+    Ok(())
+}
+
+// 1 -> 2: everything used to count rendered frames, 1 frame was 8ms
+fn frames_to_ms(json: &mut Value) -> bool {
+    for (kind, path) in template::instruments(json) {
+        let item = match json.pointer_mut(&template::pointer(&path)) {
+            Some(item) => item,
+            None => continue,
+        };
+        match kind {
+            Kind::Chord => if let Some(style) = item.get_mut("style") {
+                let name = style.get("style").and_then(Value::as_str).unwrap_or("").to_string();
+                match name.as_str() {
+                    "decay" | "decay_release" => scale(style, "time", 8),
+                    // px a frame to px a second
+                    "grow" | "marquee" => scale(style, "speed", 125),
+                    _ => {},
+                }
+            },
+            Kind::Drum => scale(item, "decay_time", 8),
+            Kind::Text => {},
+        }
+    }
+    true
+}
+
+// 2 -> 3: the port by its name rather than wherever it happened to be in the list.
+// needs it plugged in to find out what it's called
+fn name_the_port(json: &mut Value) -> bool {
+    let map = match json.as_object_mut() {
+        Some(map) if !map.contains_key("midi_port") => map,
+        _ => return true,
+    };
+    let index = match map.get("midi_port_id").and_then(Value::as_u64) {
+        Some(index) => index as usize,
+        None => return true,
+    };
+    match input::port_name(index) {
+        Ok(name) => {
+            println!("midi_port_id {} is '{}', using its name from now on", index, name);
+            map.remove("midi_port_id");
+            map.insert("midi_port".to_string(), Value::from(name));
+            true
+        },
+        Err(e) => {
+            println!("can't name midi_port_id {} ({}), staying on version 2 until it's plugged in", index, e);
+            false
+        },
+    }
+}
+
+/// multiply a whole number in an object, lengths written like "1/16" are fine as they are
+fn scale(object: &mut Value, key: &str, by: u64) {
+    if let Some(value) = object.get_mut(key) {
+        if let Some(n) = value.as_u64() {
+            *value = Value::from(n.saturating_mul(by));
+        }
+    }
+}
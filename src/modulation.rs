@@ -0,0 +1,228 @@
+use serde::{Serialize, Deserialize, Deserializer, Serializer};
+
+use crate::midi::{Channel, MidiEvent};
+use crate::setup::Setup;
+use crate::transport::TimeSpan;
+
+
+/// something in the setup that can be turned while running,
+/// written like "chord/0/xpos" (instruments count from 0)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Param {
+    Chord(usize, ChordParam),
+    Drum(usize, DrumParam),
+    Text(usize, TextParam),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChordParam {
+    Xpos,
+    Width,
+    Transpose,
+    Brightness,
+    Hue,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DrumParam {
+    Xpos,
+    Ypos,
+    DecayTime,
+    Brightness,
+    Hue,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TextParam {
+    Xpos,
+    Ypos,
+    Brightness,
+    Hue,
+}
+
+impl Param {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = s.trim_matches('/').split('/').collect();
+        if parts.len() != 3 {
+            return Err(format!("'{}' should look like chord/0/xpos", s));
+        }
+        let index = parts[1].parse::<usize>().map_err(|_| format!("bad index in '{}'", s))?;
+        let unknown = || format!("unknown parameter '{}'", s);
+        match parts[0] {
+            "chord" => Ok(Param::Chord(index, match parts[2] {
+                "xpos" => ChordParam::Xpos,
+                "width" => ChordParam::Width,
+                "transpose" => ChordParam::Transpose,
+                "brightness" => ChordParam::Brightness,
+                "hue" => ChordParam::Hue,
+                _ => return Err(unknown()),
+            })),
+            "drum" => Ok(Param::Drum(index, match parts[2] {
+                "xpos" => DrumParam::Xpos,
+                "ypos" => DrumParam::Ypos,
+                "decay_time" => DrumParam::DecayTime,
+                "brightness" => DrumParam::Brightness,
+                "hue" => DrumParam::Hue,
+                _ => return Err(unknown()),
+            })),
+            "text" => Ok(Param::Text(index, match parts[2] {
+                "xpos" => TextParam::Xpos,
+                "ypos" => TextParam::Ypos,
+                "brightness" => TextParam::Brightness,
+                "hue" => TextParam::Hue,
+                _ => return Err(unknown()),
+            })),
+            _ => Err(unknown()),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        let (kind, index, param) = match self {
+            Param::Chord(i, p) => ("chord", i, match p {
+                ChordParam::Xpos => "xpos",
+                ChordParam::Width => "width",
+                ChordParam::Transpose => "transpose",
+                ChordParam::Brightness => "brightness",
+                ChordParam::Hue => "hue",
+            }),
+            Param::Drum(i, p) => ("drum", i, match p {
+                DrumParam::Xpos => "xpos",
+                DrumParam::Ypos => "ypos",
+                DrumParam::DecayTime => "decay_time",
+                DrumParam::Brightness => "brightness",
+                DrumParam::Hue => "hue",
+            }),
+            Param::Text(i, p) => ("text", i, match p {
+                TextParam::Xpos => "xpos",
+                TextParam::Ypos => "ypos",
+                TextParam::Brightness => "brightness",
+                TextParam::Hue => "hue",
+            }),
+        };
+        format!("{}/{}/{}", kind, index, param)
+    }
+
+    /// both on the same chord/drum/text, whichever parameter
+    pub fn same_instrument(&self, other: &Param) -> bool {
+        match (self, other) {
+            (Param::Chord(a, _), Param::Chord(b, _))
+            | (Param::Drum(a, _), Param::Drum(b, _))
+            | (Param::Text(a, _), Param::Text(b, _)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// set it, positions don't go below 0. does nothing if the instrument isn't there
+    pub fn set(&self, setup: &mut Setup, value: f32) {
+        let pos = value.round().max(0.0) as u32;
+        match self {
+            Param::Chord(i, p) => if let Some(chord) = setup.chords.get_mut(*i) {
+                match p {
+                    ChordParam::Xpos => chord.xpos = pos,
+                    ChordParam::Width => chord.width = pos,
+                    ChordParam::Transpose => chord.transpose = value.round() as i32,
+                    ChordParam::Brightness => chord.tint.brightness = value,
+                    ChordParam::Hue => chord.tint.hue = value,
+                }
+            },
+            Param::Drum(i, p) => if let Some(drum) = setup.kit.drums.get_mut(*i) {
+                match p {
+                    DrumParam::Xpos => drum.xpos = pos,
+                    DrumParam::Ypos => drum.ypos = pos,
+                    DrumParam::DecayTime => drum.decay_time = TimeSpan::Ms(pos.max(1)),
+                    DrumParam::Brightness => drum.tint.brightness = value,
+                    DrumParam::Hue => drum.tint.hue = value,
+                }
+            },
+            Param::Text(i, p) => if let Some(text) = setup.texts.get_mut(*i) {
+                match p {
+                    TextParam::Xpos => text.xpos = pos,
+                    TextParam::Ypos => text.ypos = pos,
+                    TextParam::Brightness => text.tint.brightness = value,
+                    TextParam::Hue => text.tint.hue = value,
+                }
+            },
+        }
+    }
+}
+
+impl Serialize for Param {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Param {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: &str = Deserialize::deserialize(deserializer)?;
+        Param::parse(s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// how the cc value is bent on its way from min to max
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum Curve {
+    #[serde(rename = "linear")]
+    #[default]
+    Linear,
+    /// slow at first, fast at the top
+    #[serde(rename = "exponential")]
+    Exponential,
+    /// fast at first, slow at the top
+    #[serde(rename = "logarithmic")]
+    Logarithmic,
+}
+impl Curve {
+    /// 0.0..1.0 in, 0.0..1.0 out
+    pub fn apply(&self, x: f32) -> f32 {
+        match self {
+            Curve::Linear => x,
+            Curve::Exponential => x * x,
+            Curve::Logarithmic => x.sqrt(),
+        }
+    }
+}
+
+/// turn a cc into a parameter
+#[derive(Serialize, Deserialize)]
+pub struct CcMap {
+    pub channel: Channel,
+    pub cc: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+    pub target: Param,
+    pub min: f32,
+    pub max: f32,
+    #[serde(default)]
+    pub curve: Curve,
+}
+impl CcMap {
+    pub fn value_for(&self, event: &MidiEvent) -> Option<f32> {
+        if !event.is_from(&self.input) {
+            return None;
+        }
+        match event.control_change() {
+            Some((channel, cc, value)) if channel == self.channel && cc == self.cc => {
+                let x = self.curve.apply(value as f32 / 127.0);
+                Some(self.min + (self.max - self.min) * x)
+            },
+            _ => None,
+        }
+    }
+}
+
+/// move everything mapped to this cc
+pub fn apply_cc_maps(setup: &mut Setup, event: &MidiEvent) {
+    let changes: Vec<(Param, f32)> = setup.cc_maps.iter()
+        .filter_map(|map| map.value_for(event).map(|v| (map.target, v)))
+        .collect();
+    for (param, value) in changes {
+        param.set(setup, value);
+    }
+}
@@ -0,0 +1,134 @@
+use std::error::Error;
+use std::net::UdpSocket;
+use std::sync::mpsc::Sender;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use rosc::{OscMessage, OscPacket, OscType};
+
+use crate::midi::MidiEvent;
+use crate::modulation::Param;
+use crate::router::Target;
+use crate::setup::Setup;
+
+
+/// what an osc message turns into once we know the setup
+pub enum OscAction {
+    /// midi events for one instrument, in order
+    Events(Target, Vec<MidiEvent>),
+    Scene(usize),
+    Set(Param, f32),
+}
+
+/// listen for osc on localhost, passes every message (bundles unpacked) on to tx
+pub fn listen(port: u16, tx: Sender<OscMessage>) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    let socket = UdpSocket::bind(("127.0.0.1", port))?;
+    Ok(std::thread::spawn(move || {
+        let mut buf = [0u8; rosc::decoder::MTU];
+        loop {
+            let size = match socket.recv_from(&mut buf) {
+                Ok((size, _from)) => size,
+                Err(e) => {
+                    println!("osc: {}", e);
+                    continue;
+                }
+            };
+            match rosc::decoder::decode_udp(&buf[..size]) {
+                Ok((_rest, packet)) => {
+                    if !unpack(packet, &tx) {
+                        return; // nobody listening any more
+                    }
+                },
+                Err(e) => println!("osc: bad packet.. {:?}", e),
+            }
+        }
+    }))
+}
+
+// bundle timetags are ignored, everything happens as it arrives
+fn unpack(packet: OscPacket, tx: &Sender<OscMessage>) -> bool {
+    match packet {
+        OscPacket::Message(msg) => tx.send(msg).is_ok(),
+        OscPacket::Bundle(bundle) => bundle.content.into_iter().all(|p| unpack(p, tx)),
+    }
+}
+
+/// work out what a message means for this setup
+///
+///     /chord/N/note  note [velocity]    velocity 100 if left out, 0 is a note off
+///     /drum/N/hit    [velocity]         lets go straight away so it fades like a tap on a pad
+///     /text/N/next   (also line, submit, reset, and visible 0/1)
+///     /scene         index from 0, or a name
+///     /chord/N/xpos value               or any other cc_maps target
+pub fn translate(setup: &Setup, msg: &OscMessage, now: Instant) -> Result<OscAction, String> {
+    let parts: Vec<&str> = msg.addr.trim_matches('/').split('/').collect();
+    let index = || parts.get(1)
+        .and_then(|i| i.parse::<usize>().ok())
+        .ok_or(format!("bad index in '{}'", msg.addr));
+    let missing = || format!("no such instrument for '{}'", msg.addr);
+
+    match (parts[0], parts.get(2).copied()) {
+        ("scene", None) => {
+            let scene = match msg.args.first() {
+                Some(OscType::String(name)) => (0..setup.scene_count())
+                    .find(|i| setup.name_of_scene(*i) == name)
+                    .ok_or(format!("no scene called '{}'", name))?,
+                _ => number(&msg.args, 0).ok_or("/scene needs an index or a name")? as usize,
+            };
+            Ok(OscAction::Scene(scene))
+        },
+        ("chord", Some("note")) => {
+            let i = index()?;
+            let chord = setup.chords.get(i).ok_or_else(missing)?;
+            let note = number(&msg.args, 0).ok_or("/note needs a note number")?;
+            let velocity = number(&msg.args, 1).unwrap_or(100.0);
+            let source = chord.input.as_deref().unwrap_or("osc");
+            Ok(OscAction::Events(Target::Chord(i), vec![MidiEvent::note(source, now, chord.channel, midi_byte(note), midi_byte(velocity))]))
+        },
+        ("drum", Some("hit")) => {
+            let drum = setup.kit.drums.get(index()?).ok_or_else(missing)?;
+            let velocity = number(&msg.args, 0).unwrap_or(100.0);
+            let source = drum.input.as_ref().or(setup.kit.input.as_ref()).map(|s| s.as_str()).unwrap_or("osc");
+            // there's no note off coming for it, it'd stay lit
+            Ok(OscAction::Events(Target::Kit, vec![
+                MidiEvent::note(source, now, crate::midi::Channel::Ch10, drum.note, midi_byte(velocity)),
+                MidiEvent::note(source, now, crate::midi::Channel::Ch10, drum.note, 0),
+            ]))
+        },
+        ("text", Some(command @ ("line" | "next" | "submit" | "visible" | "reset"))) => {
+            let i = index()?;
+            let text = setup.texts.get(i).ok_or_else(missing)?;
+            // same notes as from the keyboard, see the text animation map in the readme
+            let (offset, velocity) = match command {
+                "line" => (0, 127),
+                "next" => (1, 127),
+                "submit" => (3, 127),
+                "visible" => (4, if number(&msg.args, 0).unwrap_or(1.0) > 0.0 {127} else {1}),
+                _ => (5, 127),
+            };
+            let source = text.input.as_deref().unwrap_or("osc");
+            Ok(OscAction::Events(Target::Text(i), vec![MidiEvent::note(source, now, text.channel, text.base_note.saturating_add(offset), velocity)]))
+        },
+        _ => {
+            let param = Param::parse(&msg.addr)?;
+            let value = number(&msg.args, 0).ok_or(format!("{} needs a value", msg.addr))?;
+            Ok(OscAction::Set(param, value))
+        }
+    }
+}
+
+/// whatever kind of number argument i is
+fn number(args: &[OscType], i: usize) -> Option<f32> {
+    match args.get(i)? {
+        OscType::Int(x) => Some(*x as f32),
+        OscType::Long(x) => Some(*x as f32),
+        OscType::Float(x) => Some(*x),
+        OscType::Double(x) => Some(*x as f32),
+        OscType::Bool(x) => Some(if *x {1.0} else {0.0}),
+        _ => None,
+    }
+}
+
+fn midi_byte(x: f32) -> u8 {
+    x.round().clamp(0.0, 127.0) as u8
+}
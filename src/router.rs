@@ -0,0 +1,121 @@
+use std::time::Instant;
+
+use crate::midi::{MidiProcessor, MidiEvent, Subscription};
+use crate::setup::Setup;
+
+
+/// which instrument in the setup a route leads to
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Target {
+    Chord(usize),
+    Kit,
+    Text(usize),
+}
+
+/// hands each message only to the instruments that asked for it
+pub struct Router {
+    routes: Vec<(Target, Subscription)>,
+}
+
+impl Router {
+    /// needs rebuilding whenever the setup changes
+    pub fn build(setup: &Setup) -> Self {
+        let mut routes = vec![];
+        for (i, chord) in setup.chords.iter().enumerate() {
+            for sub in chord.subscriptions() {
+                routes.push((Target::Chord(i), sub));
+            }
+        }
+        for sub in setup.kit.subscriptions() {
+            routes.push((Target::Kit, sub));
+        }
+        for (i, text) in setup.texts.iter().enumerate() {
+            for sub in text.subscriptions() {
+                routes.push((Target::Text(i), sub));
+            }
+        }
+        Router { routes }
+    }
+
+    /// everything that wants this event, each one once
+    pub fn targets(&self, event: &MidiEvent) -> Vec<Target> {
+        let mut targets = vec![];
+        for (target, sub) in &self.routes {
+            if !targets.contains(target) && sub.matches(event) {
+                targets.push(*target);
+            }
+        }
+        targets
+    }
+
+    pub fn dispatch(&self, setup: &mut Setup, event: MidiEvent, now: Instant) {
+        for target in self.targets(&event) {
+            Router::deliver(setup, target, event.clone(), now);
+        }
+    }
+
+    /// straight to one instrument, whatever it subscribed to
+    pub fn deliver(setup: &mut Setup, target: Target, event: MidiEvent, now: Instant) {
+        // get_mut in case the setup changed without a rebuild
+        match target {
+            Target::Chord(i) => if let Some(chord) = setup.chords.get_mut(i) {
+                chord.deal_with(event, now)
+            },
+            Target::Kit => setup.kit.deal_with(event, now),
+            Target::Text(i) => if let Some(text) = setup.texts.get_mut(i) {
+                text.deal_with(event, now)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use midi_msg::{MidiMsg, ChannelModeMsg};
+
+    use super::*;
+    use crate::midi::Channel;
+
+    fn router() -> Router {
+        let on = |channel, notes| Subscription { notes, ..Subscription::channel(channel, &None) };
+        Router { routes: vec![
+            (Target::Chord(0), on(Channel::Ch1, None)),
+            (Target::Chord(1), Subscription::channel(Channel::Ch2, &Some("keys".to_string()))),
+            (Target::Kit, on(Channel::Ch10, Some(36..=36))),
+            (Target::Kit, on(Channel::Ch10, Some(38..=38))),
+            (Target::Text(0), on(Channel::Ch1, Some(30..=36))),
+        ]}
+    }
+
+    #[test]
+    fn notes_go_by_channel_and_note() {
+        let router = router();
+        let now = Instant::now();
+        assert_eq!(router.targets(&MidiEvent::note("a", now, Channel::Ch1, 60, 100)), vec![Target::Chord(0)]);
+        assert_eq!(router.targets(&MidiEvent::note("a", now, Channel::Ch1, 31, 100)), vec![Target::Chord(0), Target::Text(0)]);
+        assert_eq!(router.targets(&MidiEvent::note("a", now, Channel::Ch10, 38, 0)), vec![Target::Kit]);
+        assert!(router.targets(&MidiEvent::note("a", now, Channel::Ch10, 40, 100)).is_empty());
+        assert!(router.targets(&MidiEvent::note("a", now, Channel::Ch3, 60, 100)).is_empty());
+    }
+
+    #[test]
+    fn only_from_the_input_asked_for() {
+        let router = router();
+        let now = Instant::now();
+        assert_eq!(router.targets(&MidiEvent::note("keys", now, Channel::Ch2, 60, 100)), vec![Target::Chord(1)]);
+        assert!(router.targets(&MidiEvent::note("pads", now, Channel::Ch2, 60, 100)).is_empty());
+    }
+
+    #[test]
+    fn all_notes_off_goes_to_everyone() {
+        // from keys, so the chord that only hears keys gets it too
+        let event = MidiEvent {
+            source: "keys".to_string(),
+            time: Instant::now(),
+            msg: MidiMsg::ChannelMode { channel: midi_msg::Channel::Ch5, msg: ChannelModeMsg::AllNotesOff },
+        };
+        assert_eq!(router().targets(&event), vec![Target::Chord(0), Target::Chord(1), Target::Kit, Target::Text(0)]);
+    }
+}
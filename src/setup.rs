@@ -1,8 +1,15 @@
-use std::{error::Error, fs::File, io::Write};
+use std::{error::Error, fs::File, io::Write, mem};
 
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use midi_msg::{MidiMsg, ChannelVoiceMsg};
 
-use crate::{chord::Chord, drum::Kit, text::Text};
+use crate::graphics::Bitmap;
+use crate::{chord::Chord, drum::{Kit, Drum, DrumGfx}, text::Text, input::PortSelector, midi::{Channel, MidiEvent}, modulation::CcMap};
+use crate::include::{self, SetupFile};
+use crate::migrate;
+use crate::template::{self, Kind, Templates};
+use crate::validate::Problems;
 
 
 #[derive(Serialize, Deserialize)]
@@ -10,29 +17,386 @@ pub struct Setup {
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
     pub path: String,
+    /// older setups are brought up to date as they're read, see migrate
+    pub version: u32,
+    /// what the file was when it was read
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub version_read: u32,
+    /// keep a copy of the file before saving over an older version (--backup)
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub backup: bool,
+    /// more setup files to take templates, chords, drums and texts from.
+    /// their instruments go ahead of the ones in here
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// the included files as they were read, so their parts can be written back to them
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub included: Vec<SetupFile>,
 
-    pub midi_port_id: usize,
+    /// part of the port name (or a regex)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub midi_port: Option<String>,
+    /// or by its position in the list, setups from before version 3 get this swapped for the name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub midi_port_id: Option<usize>,
+    /// make our own input with this name instead of looking for a port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub virtual_port: Option<String>,
+    /// --port or --virtual, wins over the three above without being saved
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub port_override: Option<PortSelector>,
+    /// several named ports at once, instruments pick one with their "input"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<InputSetup>,
+    /// which input to take midi clock and start/stop from (None takes any)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_input: Option<String>,
+    /// pass incoming midi on to another port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thru: Option<ThruSetup>,
+    /// listen for osc on this udp port (localhost only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub osc_port: Option<u16>,
+    /// tempo for beat lengths when there's no midi clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bpm: Option<f32>,
     pub global_transpose: i32,
+    /// chords, drums and texts to start from, for this file and the ones it includes
+    #[serde(default, skip_serializing_if = "Templates::is_empty")]
+    pub templates: Templates,
     pub chords: Vec<Chord>,
     pub kit: Kit,
     pub texts: Vec<Text>,
+    /// ccs that move things around
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cc_maps: Vec<CcMap>,
+    /// name of the scene made of the chords/kit/texts above
+    #[serde(default = "default_scene_name")]
+    pub scene_name: String,
+    /// more layouts to switch to, after the one above
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scenes: Vec<Scene>,
+    /// program change on this channel switches scenes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scene_channel: Option<Channel>,
+    /// 0 is the top level one. whichever is active sits in chords/kit/texts,
+    /// and the top level one waits in its slot in scenes meanwhile
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub active_scene: usize,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Scene {
+    pub name: String,
+    #[serde(default)]
+    pub chords: Vec<Chord>,
+    #[serde(default)]
+    pub kit: Kit,
+    #[serde(default)]
+    pub texts: Vec<Text>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InputSetup {
+    pub name: String,
+    /// part of the port name (or a regex)
+    /// or, for a virtual input, the name to give the port we make
+    pub port: String,
+    #[serde(rename = "virtual", default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_virtual: bool,
+}
+#[derive(Serialize, Deserialize)]
+pub struct ThruSetup {
+    /// part of the output port name (or a regex)
+    pub port: String,
+    /// only these channels go through, leave empty for all of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<Channel>,
+}
+
+impl InputSetup {
+    pub fn selector(&self) -> PortSelector {
+        if self.is_virtual {
+            PortSelector::Virtual(self.port.clone())
+        } else {
+            PortSelector::pattern(&self.port)
+        }
+    }
 }
 
 impl Setup {
-    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
-        let text = std::fs::read_to_string(path).expect("can't find setup json");
-        let mut setup: Setup = serde_json::from_str(&text)?;
+    /// every problem at once if it's no good, see validate.
+    /// a setup file without a version is taken to be `unversioned`
+    pub fn load(path: &str, unversioned: u32) -> Result<Self, Problems> {
+        let files = include::read_all(path, unversioned)?;
+        let mut templates = Templates::default();
+        for file in &files {
+            templates.add(Templates::of(&file.json));
+        }
+        // fill in from templates file by file, so problems can say where
+        let mut problems = vec![];
+        let filled: Vec<Value> = files.iter().map(|file| {
+            let mut json = file.json.clone();
+            for (at, message) in templates.fill_all(&mut json) {
+                problems.push(file.problem(&at, message));
+            }
+            json
+        }).collect();
+        if !problems.is_empty() {
+            return Err(Problems(problems));
+        }
+        let (json, from) = include::merge(filled.clone());
+        let mut setup: Setup = serde_json::from_value(json).map_err(|e| pinpoint(&files, &filled, e))?;
         setup.path = path.to_string();
+        setup.version_read = files[0].version;
+        let [chords, drums, texts] = from;
+        for (chord, from) in setup.chords.iter_mut().zip(chords) {
+            chord.from = from;
+        }
+        for (drum, from) in setup.kit.drums.iter_mut().zip(drums) {
+            drum.from = from;
+        }
+        for (text, from) in setup.texts.iter_mut().zip(texts) {
+            text.from = from;
+        }
+        setup.included = files.into_iter().skip(1).collect();
         Ok(setup)
     }
-    pub fn maybe_write(&self) -> Result<usize, Box<dyn Error>> {
-        let mut buf = Vec::new();
-        let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
-        let mut ser = serde_json::Serializer::with_formatter(&mut buf, formatter);
-        self.serialize(&mut ser).unwrap();
-        let text = String::from_utf8(buf).unwrap();
-        let mut output = File::create(&self.path)?;
-        write!(output, "{}", text)?;
-        Ok(text.len())
-    }
-}
\ No newline at end of file
+
+    /// the setup file and every file it includes
+    pub fn files(&self) -> Vec<String> {
+        std::iter::once(self.path.clone())
+            .chain(self.included.iter().map(|file| file.path.clone()))
+            .collect()
+    }
+
+    /// None means ask
+    pub fn port_selector(&self) -> Option<PortSelector> {
+        if let Some(selector) = &self.port_override {
+            return Some(selector.clone());
+        }
+        if let Some(name) = &self.virtual_port {
+            return Some(PortSelector::Virtual(name.clone()));
+        }
+        match (&self.midi_port, self.midi_port_id) {
+            (Some(pattern), _) => Some(PortSelector::pattern(pattern)),
+            (None, Some(i)) => Some(PortSelector::Index(i)),
+            (None, None) => None,
+        }
+    }
+
+    /// carry what's playing over from the setup this one replaces, in every scene.
+    /// scenes match up by name, instruments by id, or by channel/note if they don't have one
+    pub fn keep_state_from(&mut self, mut old: Setup) {
+        let active = old.active_scene;
+        // both with the top level up top, so every scene's in its own slot
+        old.arrange(0);
+        keep_scene_state(&mut self.chords, &mut self.kit.drums, &mut self.texts, &mut old.chords, &mut old.kit.drums, &mut old.texts);
+        for scene in &mut self.scenes {
+            if let Some(o) = old.scenes.iter_mut().find(|o| o.name == scene.name) {
+                keep_scene_state(&mut scene.chords, &mut scene.kit.drums, &mut scene.texts, &mut o.chords, &mut o.kit.drums, &mut o.texts);
+            }
+        }
+        if active < self.scene_count() {
+            self.arrange(active);
+        }
+    }
+
+    /// every drum in every scene
+    fn all_drums_mut(&mut self) -> impl Iterator<Item = &mut Drum> {
+        self.kit.drums.iter_mut()
+            .chain(self.scenes.iter_mut().flat_map(|scene| scene.kit.drums.iter_mut()))
+    }
+
+    /// every image file the setup uses, each once
+    pub fn bitmap_paths(&self) -> Vec<String> {
+        let drums = self.kit.drums.iter()
+            .chain(self.scenes.iter().flat_map(|scene| scene.kit.drums.iter()));
+        let mut paths: Vec<String> = vec![];
+        for drum in drums {
+            if let DrumGfx::Bitmap { bitmap } = &drum.gfx {
+                if !paths.contains(&bitmap.path) {
+                    paths.push(bitmap.path.clone());
+                }
+            }
+        }
+        paths
+    }
+
+    /// read an image again for every drum that uses it, returns how many
+    pub fn reload_bitmap(&mut self, path: &str) -> Result<usize, Box<dyn Error>> {
+        let fresh = Bitmap::load_from_file(path)?;
+        let mut count = 0;
+        for drum in self.all_drums_mut() {
+            if let DrumGfx::Bitmap { bitmap } = &mut drum.gfx {
+                if bitmap.path == path {
+                    *bitmap = fresh.clone();
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn scene_count(&self) -> usize {
+        self.scenes.len() + 1
+    }
+
+    pub fn name_of_scene(&self, index: usize) -> &str {
+        if index == 0 {
+            &self.scene_name
+        } else {
+            &self.scenes[index - 1].name
+        }
+    }
+
+    /// swap the top level with a slot in scenes, names stay put
+    fn swap_scene(&mut self, index: usize) {
+        let slot = &mut self.scenes[index - 1];
+        mem::swap(&mut self.chords, &mut slot.chords);
+        mem::swap(&mut self.kit, &mut slot.kit);
+        mem::swap(&mut self.texts, &mut slot.texts);
+    }
+
+    /// get a scene up top without touching anything playing
+    fn arrange(&mut self, index: usize) {
+        if self.active_scene != 0 {
+            self.swap_scene(self.active_scene); // put it back
+        }
+        if index != 0 {
+            self.swap_scene(index);
+        }
+        self.active_scene = index;
+    }
+
+    /// false if there's no such scene
+    pub fn switch_scene(&mut self, index: usize) -> bool {
+        if index >= self.scene_count() {
+            return false;
+        }
+        if index == self.active_scene {
+            return true;
+        }
+        // let go of anything playing, it won't hear the note offs from over there
+        for chord in &mut self.chords {
+            chord.notes.clear();
+        }
+        for drum in &mut self.kit.drums {
+            drum.state.reset();
+        }
+        self.arrange(index);
+        true
+    }
+
+    /// the scene a program change asks for, if it's on the scene channel
+    pub fn scene_change(&self, event: &MidiEvent) -> Option<usize> {
+        let scene_channel = self.scene_channel?;
+        match event.msg {
+            MidiMsg::ChannelVoice { channel, msg: ChannelVoiceMsg::ProgramChange { program } } => {
+                if channel as u8 == scene_channel as u8 {
+                    Some(program as usize)
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /// writes the included files too, each with its own instruments back in it.
+    /// gives back how much was written altogether
+    pub fn maybe_write(&mut self) -> Result<usize, Box<dyn Error>> {
+        // write it out the way it was read, top level scene up top
+        let active = self.active_scene;
+        self.arrange(0);
+        let json = serde_json::to_value(&*self);
+        let from = [
+            self.chords.iter().map(|chord| chord.from).collect(),
+            self.kit.drums.iter().map(|drum| drum.from).collect(),
+            self.texts.iter().map(|text| text.from).collect(),
+        ];
+        self.arrange(active);
+        let mut json = json?;
+
+        let mut included: Vec<Value> = self.included.iter().map(|file| file.json.clone()).collect();
+        include::split(&mut json, &from, &mut included);
+        let mut templates = self.templates.clone();
+        for file in &self.included {
+            templates.add(Templates::of(&file.json));
+        }
+        templates.strip_all(&mut json);
+        if self.backup {
+            migrate::backup(&self.path, self.version_read, self.version)?;
+            for file in &self.included {
+                migrate::backup(&file.path, file.version, migrate::version_of(&file.json))?;
+            }
+        }
+        let mut length = write_json(&self.path, &json)?;
+        self.version_read = self.version;
+        for (file, mut json) in self.included.iter_mut().zip(included) {
+            templates.strip_all(&mut json);
+            length += write_json(&file.path, &json)?;
+            file.version = migrate::version_of(&json);
+            file.json = json;
+        }
+        Ok(length)
+    }
+}
+
+fn write_json(path: &str, json: &Value) -> Result<usize, Box<dyn Error>> {
+    let mut buf = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+    let mut ser = serde_json::Serializer::with_formatter(&mut buf, formatter);
+    json.serialize(&mut ser)?;
+    let text = String::from_utf8(buf)?;
+    let mut output = File::create(path)?;
+    write!(output, "{}", text)?;
+    Ok(text.len())
+}
+
+/// serde can't say where it went wrong in a json value, so find the first
+/// instrument that won't load on its own and say where that is
+fn pinpoint(files: &[SetupFile], filled: &[Value], e: serde_json::Error) -> Problems {
+    for (file, json) in files.iter().zip(filled) {
+        for (kind, at) in template::instruments(json) {
+            let item = json.pointer(&template::pointer(&at)).cloned().unwrap_or_default();
+            let error = match kind {
+                Kind::Chord => serde_json::from_value::<Chord>(item).err(),
+                Kind::Drum => serde_json::from_value::<Drum>(item).err(),
+                Kind::Text => serde_json::from_value::<Text>(item).err(),
+            };
+            if let Some(error) = error {
+                return Problems(vec![file.problem(&at, error.to_string())]);
+            }
+        }
+    }
+    Problems::from_serde(e)
+}
+
+fn default_scene_name() -> String {
+    "main".to_string()
+}
+
+/// one scene's worth of pair_up, takes the old ones
+fn keep_scene_state(chords: &mut [Chord], drums: &mut [Drum], texts: &mut [Text], old_chords: &mut Vec<Chord>, old_drums: &mut Vec<Drum>, old_texts: &mut Vec<Text>) {
+    pair_up(chords, mem::take(old_chords), Chord::identity, Chord::keep_state_from);
+    pair_up(drums, mem::take(old_drums), Drum::identity, Drum::keep_state_from);
+    pair_up(texts, mem::take(old_texts), Text::identity, Text::keep_state_from);
+}
+
+/// give each new one the state of the first old one that's the same, each old one only once
+fn pair_up<T>(new: &mut [T], old: Vec<T>, identity: fn(&T) -> String, keep: fn(&mut T, T)) {
+    let mut old: Vec<(String, Option<T>)> = old.into_iter().map(|o| (identity(&o), Some(o))).collect();
+    for n in new.iter_mut() {
+        let id = identity(n);
+        let found = old.iter_mut().find(|(old_id, o)| o.is_some() && *old_id == id);
+        if let Some((_, o)) = found {
+            keep(n, o.take().unwrap());
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+
+
+/// how deep templates can build on each other, so going round in circles stops somewhere
+const MAX_DEPTH: usize = 16;
+
+/// what there can be templates for
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Chord,
+    Drum,
+    Text,
+}
+impl Kind {
+    pub const ALL: [Kind; 3] = [Kind::Chord, Kind::Drum, Kind::Text];
+
+    /// where they're kept in a setup or scene
+    pub fn list(&self) -> &'static str {
+        match self {
+            Kind::Chord => "chords",
+            Kind::Drum => "kit.drums",
+            Kind::Text => "texts",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Chord => "chord",
+            Kind::Drum => "drum",
+            Kind::Text => "text",
+        }
+    }
+}
+
+/// named starting points for chords, drums and texts. one that says "template": "name"
+/// gets everything it doesn't say itself from there (objects like style key by key).
+/// a template can have a "template" too, to build on another one
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Templates {
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub chords: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub drums: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub texts: Map<String, Value>,
+}
+
+impl Templates {
+    pub fn is_empty(&self) -> bool {
+        self.chords.is_empty() && self.drums.is_empty() && self.texts.is_empty()
+    }
+
+    /// the ones in a setup file's json (already checked by validate)
+    pub fn of(json: &Value) -> Templates {
+        json.get("templates")
+            .and_then(|templates| serde_json::from_value(templates.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    /// put another file's in with these, if two have the same name the one we've got wins
+    pub fn add(&mut self, other: Templates) {
+        for (mine, theirs) in [(&mut self.chords, other.chords), (&mut self.drums, other.drums), (&mut self.texts, other.texts)] {
+            for (name, template) in theirs {
+                mine.entry(name).or_insert(template);
+            }
+        }
+    }
+
+    fn named(&self, kind: Kind) -> &Map<String, Value> {
+        match kind {
+            Kind::Chord => &self.chords,
+            Kind::Drum => &self.drums,
+            Kind::Text => &self.texts,
+        }
+    }
+
+    /// fill in every instrument in a setup file (and its scenes) that names a template.
+    /// gives back (path, message) for the ones that can't be
+    pub fn fill_all(&self, json: &mut Value) -> Vec<(String, String)> {
+        let mut problems = vec![];
+        for (kind, path) in instruments(json) {
+            if let Some(item) = json.pointer_mut(&pointer(&path)) {
+                if let Err(e) = self.fill(kind, item) {
+                    problems.push((format!("{}.template", path), e));
+                }
+            }
+        }
+        problems
+    }
+
+    /// leave out whatever's the same as in the template, undoes fill_all
+    pub fn strip_all(&self, json: &mut Value) {
+        for (kind, path) in instruments(json) {
+            if let Some(item) = json.pointer_mut(&pointer(&path)) {
+                self.strip(kind, item);
+            }
+        }
+    }
+
+    fn fill(&self, kind: Kind, item: &mut Value) -> Result<(), String> {
+        let name = match item.get("template") {
+            None => return Ok(()),
+            Some(Value::String(name)) => name.clone(),
+            Some(_) => return Err("should be the name of a template".to_string()),
+        };
+        let mut full = self.resolve(kind, &name, 0)?;
+        overlay(&mut full, item.take());
+        *item = full;
+        Ok(())
+    }
+
+    fn strip(&self, kind: Kind, item: &mut Value) {
+        let name = match item.get("template") {
+            Some(Value::String(name)) => name.clone(),
+            _ => return,
+        };
+        let full = match self.resolve(kind, &name, 0) {
+            Ok(full) => full,
+            Err(_) => return,
+        };
+        // serde leaves out a None or an empty list, but then the template would fill it back in
+        let mut left_out_here = vec![];
+        if let (Value::Object(map), Value::Object(full)) = (&*item, &full) {
+            for (key, value) in full {
+                if !map.contains_key(key) {
+                    left_out_here.push((key.clone(), left_out(value)));
+                }
+            }
+        }
+        remove_same(item, &full);
+        if let Value::Object(map) = item {
+            map.extend(left_out_here);
+        }
+    }
+
+    /// a template with whatever it builds on filled in
+    fn resolve(&self, kind: Kind, name: &str, depth: usize) -> Result<Value, String> {
+        let template = self.named(kind).get(name)
+            .ok_or(format!("no {} template called '{}'", kind.name(), name))?;
+        if depth > MAX_DEPTH {
+            return Err(format!("{} templates go round in a circle at '{}'", kind.name(), name));
+        }
+        let mut full = match template.get("template") {
+            Some(Value::String(base)) => self.resolve(kind, base, depth + 1)?,
+            _ => Value::Object(Map::new()),
+        };
+        overlay(&mut full, template.clone());
+        if let Some(map) = full.as_object_mut() {
+            map.remove("template");
+        }
+        Ok(full)
+    }
+}
+
+/// where every chord, drum and text in a setup file is, like "scenes[1].kit.drums[2]"
+pub fn instruments(json: &Value) -> Vec<(Kind, String)> {
+    let mut scenes = vec![(String::new(), json)];
+    if let Some(Value::Array(list)) = json.get("scenes") {
+        scenes.extend(list.iter().enumerate().map(|(i, scene)| (format!("scenes[{}].", i), scene)));
+    }
+    let mut found = vec![];
+    for (prefix, scene) in scenes {
+        for kind in Kind::ALL {
+            if let Some(Value::Array(list)) = scene.pointer(&pointer(kind.list())) {
+                found.extend((0..list.len()).map(|i| (kind, format!("{}{}[{}]", prefix, kind.list(), i))));
+            }
+        }
+    }
+    found
+}
+
+/// one of the paths above as a json pointer, "scenes[1].kit" is "/scenes/1/kit"
+pub fn pointer(path: &str) -> String {
+    format!("/{}", path.replace("].", "/").replace(['.', '['], "/").trim_end_matches(']'))
+}
+
+/// top over base, objects go key by key and anything else replaces
+fn overlay(base: &mut Value, top: Value) {
+    match (base, top) {
+        (Value::Object(base), Value::Object(top)) => {
+            for (key, value) in top {
+                match base.get_mut(&key) {
+                    Some(under) => overlay(under, value),
+                    None => {
+                        base.insert(key, value);
+                    },
+                }
+            }
+        },
+        (base, top) => *base = top,
+    }
+}
+
+/// take out of value what base already says
+fn remove_same(value: &mut Value, base: &Value) {
+    if let (Value::Object(map), Value::Object(base)) = (value, base) {
+        map.retain(|key, v| match base.get(key) {
+            Some(b) if same(v, b) => false,
+            Some(b) => {
+                remove_same(v, b);
+                // nothing left that's different
+                !(b.is_object() && v.as_object().is_some_and(Map::is_empty))
+            },
+            None => true,
+        });
+    }
+}
+
+/// what a field serde didn't write reads back as
+fn left_out(in_template: &Value) -> Value {
+    match in_template {
+        Value::Array(_) => Value::Array(vec![]),
+        _ => Value::Null,
+    }
+}
+
+// 2 in the template is the same as the 2.0 an f32 comes out as
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => a == b,
+    }
+}
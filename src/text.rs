@@ -1,8 +1,10 @@
+use std::time::Instant;
+
 use serde::{Serialize, Deserialize};
 use midi_msg::{MidiMsg, ChannelVoiceMsg};
 
 use crate::midi::*;
-use crate::graphics::RGB;
+use crate::graphics::{RGB, Tint};
 
 #[derive(Serialize, Deserialize)]
 pub enum TextStyle {
@@ -24,9 +26,21 @@ pub enum TextDirection {
 
 #[derive(Serialize, Deserialize)]
 pub struct Text {
+    /// keeps its state across reloads even if everything else about it changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// what it was filled in from, see template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// which of setup.included it's from, None if it's in the setup itself
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub from: Option<usize>,
     pub xpos: u32,
     pub ypos: u32,
     pub channel: Channel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
     pub base_note: u8,
     pub direction: TextDirection,
     pub lines: Vec<String>,
@@ -35,6 +49,9 @@ pub struct Text {
     pub visibility: bool,
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
+    pub tint: Tint,
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
     pub current_line: usize,
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
@@ -43,20 +60,41 @@ pub struct Text {
     #[serde(skip_deserializing)]
     buffered_progress: usize,
 }
+impl Text {
+    /// what makes it the same text after a reload
+    pub fn identity(&self) -> String {
+        match &self.id {
+            Some(id) => format!("id {}", id),
+            None => format!("ch {} note {} {:?}", self.channel as u8 + 1, self.base_note, self.input),
+        }
+    }
+
+    /// stay on the same line (or the last one, if there's fewer now)
+    pub fn keep_state_from(&mut self, old: Text) {
+        self.current_line = old.current_line.min(self.lines.len().saturating_sub(1));
+        self.line_progress = old.line_progress;
+        self.buffered_progress = old.buffered_progress;
+        self.tint = old.tint;
+    }
+}
+
 impl MidiProcessor for Text {
-    fn deal_with(&mut self, message: MidiMsg) {
-        match message {
+    fn deal_with(&mut self, event: MidiEvent, _now: Instant) {
+        if !event.is_from(&self.input) {
+            return;
+        }
+        match event.msg {
             MidiMsg::ChannelVoice {channel, msg} => {
                 if channel as u8 == self.channel as u8 {
                     match msg {
                         ChannelVoiceMsg::NoteOn { note, velocity } => {
-                            let n_base = 30;
-                            let n_next = n_base + 1;
-                            let n_two = n_base + 2;
-                            let n_submit = n_base + 3;
-                            let n_visible = n_base + 4;
-                            let n_reset = n_base + 5;
-                            let n_mode = n_base + 6;
+                            let n_base = self.base_note;
+                            let n_next = n_base.saturating_add(1);
+                            let n_two = n_base.saturating_add(2);
+                            let n_submit = n_base.saturating_add(3);
+                            let n_visible = n_base.saturating_add(4);
+                            let n_reset = n_base.saturating_add(5);
+                            let n_mode = n_base.saturating_add(6);
                             
                             match note {
                                 _x if note == n_base => {
@@ -124,4 +162,12 @@ impl MidiProcessor for Text {
             _ => {}
         }
     }
+
+    fn subscriptions(&self) -> Vec<Subscription> {
+        // base note and the 6 above it
+        vec![Subscription {
+            notes: Some(self.base_note..=self.base_note.saturating_add(6)),
+            ..Subscription::channel(self.channel, &self.input)
+        }]
+    }
 }
\ No newline at end of file
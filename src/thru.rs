@@ -0,0 +1,57 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use midir::{MidiOutput, MidiOutputConnection};
+
+use crate::input::PortSelector;
+use crate::midi::Channel;
+use crate::setup::ThruSetup;
+
+
+/// copies incoming midi out to another port, so we can sit between a controller and a synth
+pub struct Thru {
+    pub port_name: String,
+    conn: Mutex<MidiOutputConnection>,
+    /// only pass these channels (empty passes all). system messages always go through
+    channels: Vec<Channel>,
+}
+
+impl Thru {
+    pub fn open(setup: &ThruSetup) -> Result<Arc<Self>, Box<dyn Error>> {
+        let midi_out = MidiOutput::new("grooveanim thru")?;
+        let selector = PortSelector::pattern(&setup.port);
+        let mut found = None;
+        for (i, port) in midi_out.ports().iter().enumerate() {
+            let name = midi_out.port_name(port)?;
+            if selector.matches(i, &name) {
+                found = Some((port.clone(), name));
+                break;
+            }
+        }
+        let (port, port_name) = found.ok_or(format!("no output port matching {}", selector))?;
+        let conn = midi_out.connect(&port, "grooveanim-thru")?;
+        Ok(Arc::new(Thru {
+            port_name,
+            conn: Mutex::new(conn),
+            channels: setup.channels.clone(),
+        }))
+    }
+
+    pub fn forward(&self, message: &[u8]) {
+        let status = match message.first() {
+            Some(s) => *s,
+            None => return,
+        };
+        if status < 0xF0 && !self.channels.is_empty() {
+            let channel = Channel::from(status & 0x0F);
+            if !self.channels.contains(&channel) {
+                return;
+            }
+        }
+        if let Ok(mut conn) = self.conn.lock() {
+            if let Err(e) = conn.send(message) {
+                println!("thru: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use midi_msg::{MidiMsg, SystemRealTimeMsg, SystemCommonMsg};
+use serde::{Serialize, Deserialize, Deserializer, Serializer};
+
+use crate::midi::{MidiProcessor, MidiEvent};
+
+
+/// midi clock sends 24 of these per beat
+pub const TICKS_PER_BEAT: u32 = 24;
+/// how many clock intervals to average the tempo over (two beats)
+const TEMPO_WINDOW: usize = 48;
+/// if there's no clock and no bpm in the setup
+const DEFAULT_BPM: f32 = 120.0;
+
+/// where the song is, worked out from midi clock and start/stop/continue/song position
+pub struct Transport {
+    /// only follow the clock from this input (None follows any)
+    pub input: Option<String>,
+    pub playing: bool,
+    /// clock ticks since the start of the song
+    pub ticks: u32,
+    pub beats_per_bar: u32,
+    /// None until enough clock has come in to tell
+    pub bpm: Option<f32>,
+    /// the setup's bpm, for when there's no clock
+    pub fixed_bpm: Option<f32>,
+    last_clock: Option<Instant>,
+    intervals: VecDeque<Duration>,
+    // the first clock after start/continue is the position itself, not a step forward
+    hold_next_tick: bool,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Transport {
+            input: None,
+            playing: false,
+            ticks: 0,
+            beats_per_bar: 4,
+            bpm: None,
+            fixed_bpm: None,
+            last_clock: None,
+            intervals: VecDeque::with_capacity(TEMPO_WINDOW),
+            hold_next_tick: false,
+        }
+    }
+
+    /// clock if there is one, then the setup's, then 120
+    pub fn tempo(&self) -> f32 {
+        self.bpm.or(self.fixed_bpm).unwrap_or(DEFAULT_BPM)
+    }
+
+    /// how long a beat lasts right now
+    pub fn beat_ms(&self) -> f32 {
+        60_000.0 / self.tempo()
+    }
+
+    /// beats since the start of the song
+    pub fn beat(&self) -> u32 {
+        self.ticks / TICKS_PER_BEAT
+    }
+
+    /// bars since the start of the song
+    pub fn bar(&self) -> u32 {
+        self.beat() / self.beats_per_bar
+    }
+
+    /// which beat of the bar we're on (from 0)
+    pub fn beat_in_bar(&self) -> u32 {
+        self.beat() % self.beats_per_bar
+    }
+
+    /// how far through the current beat, 0.0 to 1.0
+    pub fn beat_phase(&self, now: Instant) -> f32 {
+        let mut phase = (self.ticks % TICKS_PER_BEAT) as f32 / TICKS_PER_BEAT as f32;
+        // fill in between clock ticks so things don't step
+        if let (true, Some(bpm), Some(last)) = (self.playing, self.bpm, self.last_clock) {
+            let tick_length = 60.0 / bpm / TICKS_PER_BEAT as f32;
+            let since = now.saturating_duration_since(last).as_secs_f32().min(tick_length);
+            phase += since / tick_length / TICKS_PER_BEAT as f32;
+        }
+        phase.min(1.0)
+    }
+
+    fn clock(&mut self, time: Instant) {
+        if let Some(last) = self.last_clock {
+            if self.intervals.len() == TEMPO_WINDOW {
+                self.intervals.pop_front();
+            }
+            self.intervals.push_back(time.saturating_duration_since(last));
+            // wait for a whole beat before saying anything
+            if self.intervals.len() >= TICKS_PER_BEAT as usize {
+                let total: Duration = self.intervals.iter().sum();
+                let tick = total.as_secs_f32() / self.intervals.len() as f32;
+                self.bpm = Some(60.0 / (tick * TICKS_PER_BEAT as f32));
+            }
+        }
+        self.last_clock = Some(time);
+
+        if self.playing {
+            if self.hold_next_tick {
+                self.hold_next_tick = false;
+            } else {
+                self.ticks += 1;
+            }
+        }
+    }
+}
+
+impl MidiProcessor for Transport {
+    fn deal_with(&mut self, event: MidiEvent, _now: Instant) {
+        if !event.is_from(&self.input) {
+            return;
+        }
+        match event.msg {
+            MidiMsg::SystemRealTime { msg } => {
+                match msg {
+                    SystemRealTimeMsg::TimingClock => self.clock(event.time),
+                    SystemRealTimeMsg::Start => {
+                        self.playing = true;
+                        self.ticks = 0;
+                        self.hold_next_tick = true;
+                    },
+                    SystemRealTimeMsg::Continue => {
+                        self.playing = true;
+                        self.hold_next_tick = true;
+                    },
+                    SystemRealTimeMsg::Stop => {
+                        self.playing = false;
+                    },
+                    _ => {}
+                }
+            },
+            MidiMsg::SystemCommon { msg: SystemCommonMsg::SongPosition(pos) } => {
+                // counted in sixteenths, 6 clocks each
+                self.ticks = pos as u32 * 6;
+            },
+            _ => {}
+        }
+    }
+}
+
+/// a length of time, either fixed or in musical units that stretch with the tempo.
+/// in the json a number is ms, or a string like "250ms", "1/16", "1 beat", "2 bars"
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimeSpan {
+    Ms(u32),
+    /// 1/N of a bar of 4/4, so 1/4 is a beat
+    Note(u32),
+    Beats(f32),
+    Bars(f32),
+}
+impl TimeSpan {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let bad = || format!("'{}' should be like 250ms, 1/16, 1 beat or 2 bars", s);
+        if let Some(n) = s.strip_prefix("1/") {
+            return match n.trim().parse::<u32>() {
+                Ok(n) if n > 0 => Ok(TimeSpan::Note(n)),
+                _ => Err(bad()),
+            };
+        }
+        if let Some(ms) = s.strip_suffix("ms") {
+            return ms.trim().parse::<u32>().map(TimeSpan::Ms).map_err(|_| bad());
+        }
+        let (amount, unit) = s.split_once(' ').ok_or_else(bad)?;
+        let amount = amount.parse::<f32>().map_err(|_| bad())?;
+        match unit.trim() {
+            "beat" | "beats" => Ok(TimeSpan::Beats(amount)),
+            "bar" | "bars" => Ok(TimeSpan::Bars(amount)),
+            _ => Err(bad()),
+        }
+    }
+
+    /// how long it is at the transport's tempo
+    pub fn ms(&self, transport: &Transport) -> f32 {
+        match self {
+            TimeSpan::Ms(ms) => *ms as f32,
+            TimeSpan::Note(n) => transport.beat_ms() * 4.0 / *n as f32,
+            TimeSpan::Beats(beats) => transport.beat_ms() * beats,
+            TimeSpan::Bars(bars) => transport.beat_ms() * bars * transport.beats_per_bar as f32,
+        }
+    }
+}
+impl fmt::Display for TimeSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |x: &f32| if (x - 1.0).abs() < f32::EPSILON {""} else {"s"};
+        match self {
+            TimeSpan::Ms(ms) => write!(f, "{}ms", ms),
+            TimeSpan::Note(n) => write!(f, "1/{}", n),
+            TimeSpan::Beats(beats) => write!(f, "{} beat{}", beats, plural(beats)),
+            TimeSpan::Bars(bars) => write!(f, "{} bar{}", bars, plural(bars)),
+        }
+    }
+}
+
+// plain ms stay numbers so older setups keep working
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrText {
+    Number(u32),
+    Text(String),
+}
+
+impl Serialize for TimeSpan {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            TimeSpan::Ms(ms) => serializer.serialize_u32(*ms),
+            _ => serializer.serialize_str(&self.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeSpan {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match NumberOrText::deserialize(deserializer)? {
+            NumberOrText::Number(ms) => Ok(TimeSpan::Ms(ms)),
+            NumberOrText::Text(s) => TimeSpan::parse(&s).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// how fast something moves across the window (or grows), px per second or how long the trip takes
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Speed {
+    PxPerSec(u32),
+    Crossing(TimeSpan),
+}
+impl Speed {
+    /// px per ms, the trip being distance px long
+    pub fn px_per_ms(&self, transport: &Transport, distance: u32) -> f32 {
+        match self {
+            Speed::PxPerSec(px) => *px as f32 / 1000.0,
+            Speed::Crossing(span) => distance as f32 / span.ms(transport).max(1.0),
+        }
+    }
+}
+
+impl Serialize for Speed {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Speed::PxPerSec(px) => serializer.serialize_u32(*px),
+            // always a string, a bare number would read back as px per second
+            Speed::Crossing(span) => serializer.serialize_str(&span.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Speed {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match NumberOrText::deserialize(deserializer)? {
+            NumberOrText::Number(px) => Ok(Speed::PxPerSec(px)),
+            NumberOrText::Text(s) => TimeSpan::parse(&s).map(Speed::Crossing).map_err(serde::de::Error::custom),
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::midi::Channel;
+use crate::migrate::VERSION;
+use crate::transport::TimeSpan;
+
+
+/// something wrong in a setup file, and where
+pub struct Problem {
+    /// which setup file, empty if it's not down to one
+    pub file: String,
+    /// like chords[2].style.style, empty if it's the whole file
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.file.is_empty() {
+            write!(f, "{}:", self.file)?;
+        }
+        if self.path.is_empty() {
+            write!(f, "{}:{} {}", self.line, self.column, self.message)
+        } else {
+            write!(f, "{}:{} {}: {}", self.line, self.column, self.path, self.message)
+        }
+    }
+}
+
+/// everything wrong with a setup, so they can all be fixed in one go
+pub struct Problems(pub Vec<Problem>);
+impl Problems {
+    pub fn file(path: &str, e: std::io::Error) -> Self {
+        Problems(vec![Problem { file: String::new(), path: String::new(), line: 0, column: 0, message: format!("can't read {}: {}", path, e) }])
+    }
+
+    /// whatever serde tripped over that the checks below didn't catch
+    pub fn from_serde(e: serde_json::Error) -> Self {
+        let text = e.to_string();
+        // it says where itself, we've got line and column separately
+        let message = text.split(" at line ").next().unwrap_or(&text).to_string();
+        Problems(vec![Problem { file: String::new(), path: String::new(), line: e.line(), column: e.column(), message }])
+    }
+}
+impl fmt::Display for Problems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Debug for Problems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+impl Error for Problems {}
+
+const CHORD_STYLES: [&str; 5] = ["plain", "decay", "decay_release", "grow", "marquee"];
+const DRUM_STYLES: [&str; 2] = ["plain", "decay"];
+const DRUM_GFX: [&str; 2] = ["plain", "bitmap"];
+const TEXT_STYLES: [&str; 4] = ["ByLine", "ByWord", "ByCharacter", "ByCharacterUntilSubmit"];
+const TEXT_DIRECTIONS: [&str; 3] = ["horizontal", "vertical", "diagonal"];
+
+/// look through a setup's json for everything that would stop it loading (or crash later)
+pub fn check(text: &str) -> Vec<Problem> {
+    let root: Value = match serde_json::from_str(text) {
+        Ok(v) => v,
+        Err(e) => return Problems::from_serde(e).0,
+    };
+    let mut checker = Checker::new(text);
+
+    checker.channels(&root, "");
+    match root.get("version").map(Value::as_u64) {
+        None => {},
+        Some(Some(v)) if (1..=VERSION as u64).contains(&v) => {},
+        Some(Some(v)) if v > VERSION as u64 => {
+            checker.report("version", format!("made by a newer grooveanim, this one reads up to version {}", VERSION));
+        },
+        Some(_) => checker.report("version", "should be a whole number from 1".to_string()),
+    }
+    match root.get("include") {
+        None => {},
+        Some(Value::Array(files)) if files.iter().all(Value::is_string) => {},
+        Some(_) => checker.report("include", "should be a list of file names".to_string()),
+    }
+    if let Some(templates) = root.get("templates") {
+        checker.templates(templates);
+    }
+    checker.scene(&root, "");
+    if let Some(Value::Array(scenes)) = root.get("scenes") {
+        for (i, scene) in scenes.iter().enumerate() {
+            checker.scene(scene, &format!("scenes[{}]", i));
+        }
+    }
+    checker.problems
+}
+
+/// a problem found some other way, at path in text
+pub fn locate(text: &str, path: &str, message: String) -> Problem {
+    let mut checker = Checker::new(text);
+    checker.report(path, message);
+    checker.problems.remove(0)
+}
+
+struct Checker<'a> {
+    text: &'a str,
+    /// where each value starts in the text, by path
+    spots: HashMap<String, usize>,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(text: &'a str) -> Self {
+        let mut spots = HashMap::new();
+        scan(text.as_bytes(), 0, "", &mut spots);
+        Checker { text, spots, problems: vec![] }
+    }
+
+    fn report(&mut self, path: &str, message: String) {
+        // fall back to the closest parent that's there
+        let mut at = path;
+        let offset = loop {
+            if let Some(offset) = self.spots.get(at) {
+                break *offset;
+            }
+            match at.rfind(['.', '[']) {
+                Some(i) => at = &at[..i],
+                None => break 0,
+            }
+        };
+        let before = &self.text[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        self.problems.push(Problem { file: String::new(), path: path.to_string(), line, column, message });
+    }
+
+    /// every channel anywhere in the file
+    fn channels(&mut self, value: &Value, path: &str) {
+        match value {
+            Value::Object(map) => {
+                for (key, v) in map {
+                    let path = join(path, key);
+                    match (key.as_str(), v) {
+                        ("channel" | "scene_channel", _) => self.channel(v, &path),
+                        ("note_channels" | "channels", Value::Array(list)) => {
+                            for (i, c) in list.iter().enumerate() {
+                                self.channel(c, &format!("{}[{}]", path, i));
+                            }
+                        },
+                        _ => self.channels(v, &path),
+                    }
+                }
+            },
+            Value::Array(list) => {
+                for (i, v) in list.iter().enumerate() {
+                    self.channels(v, &format!("{}[{}]", path, i));
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn channel(&mut self, value: &Value, path: &str) {
+        match value {
+            Value::String(s) => if let Err(e) = Channel::parse(s) {
+                self.report(path, e);
+            },
+            _ => self.report(path, "channel should be a string like \"10\"".to_string()),
+        }
+    }
+
+    /// the chords, kit and texts at the top or in a scene
+    fn scene(&mut self, scene: &Value, path: &str) {
+        if let Some(Value::Array(chords)) = scene.get("chords") {
+            for (i, chord) in chords.iter().enumerate() {
+                self.chord(chord, &format!("{}[{}]", join(path, "chords"), i));
+            }
+        }
+        if let Some(Value::Array(drums)) = scene.get("kit").and_then(|kit| kit.get("drums")) {
+            for (i, drum) in drums.iter().enumerate() {
+                self.drum(drum, &format!("{}[{}]", join(path, "kit.drums"), i));
+            }
+        }
+        if let Some(Value::Array(texts)) = scene.get("texts") {
+            for (i, text) in texts.iter().enumerate() {
+                self.text(text, &format!("{}[{}]", join(path, "texts"), i));
+            }
+        }
+    }
+
+    /// checked like the instruments themselves, they just don't have to have everything
+    fn templates(&mut self, templates: &Value) {
+        let kinds = match templates {
+            Value::Object(kinds) => kinds,
+            _ => return self.report("templates", "should be like {\"chords\": {\"name\": {...}}}".to_string()),
+        };
+        for (kind, named) in kinds {
+            let path = join("templates", kind);
+            let named = match named {
+                Value::Object(named) => named,
+                _ => {
+                    self.report(&path, "should be templates by name".to_string());
+                    continue;
+                },
+            };
+            for (name, template) in named {
+                let at = join(&path, name);
+                match kind.as_str() {
+                    "chords" => self.chord(template, &at),
+                    "drums" => self.drum(template, &at),
+                    "texts" => self.text(template, &at),
+                    _ => {
+                        self.report(&path, format!("unknown kind of template {}, should be chords, drums or texts", kind));
+                        break;
+                    },
+                }
+            }
+        }
+    }
+
+    fn chord(&mut self, chord: &Value, path: &str) {
+        let style = match chord.get("style") {
+            Some(style) => style,
+            None => return,
+        };
+        let path = join(path, "style");
+        let name = self.one_of(style.get("style"), &join(&path, "style"), &CHORD_STYLES);
+        match name.as_deref() {
+            Some("decay" | "decay_release") => if let Some(time) = style.get("time") {
+                self.span(time, &join(&path, "time"));
+            },
+            Some("grow" | "marquee") => if let Some(Value::String(s)) = style.get("speed") {
+                if let Err(e) = TimeSpan::parse(s) {
+                    self.report(&join(&path, "speed"), e);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn drum(&mut self, drum: &Value, path: &str) {
+        if let Some(time) = drum.get("decay_time") {
+            self.span(time, &join(path, "decay_time"));
+        }
+        self.one_of(drum.get("style"), &join(path, "style"), &DRUM_STYLES);
+        if let Some(gfx) = drum.get("gfx") {
+            let gfx_path = join(path, "gfx");
+            let name = self.one_of(gfx.get("gfx"), &join(&gfx_path, "gfx"), &DRUM_GFX);
+            if name.as_deref() == Some("bitmap") {
+                if let Some(Value::String(file)) = gfx.get("bitmap") {
+                    if !Path::new(file).exists() {
+                        self.report(&join(&gfx_path, "bitmap"), format!("can't find image '{}'", file));
+                    }
+                }
+            }
+        }
+    }
+
+    fn text(&mut self, text: &Value, path: &str) {
+        // it takes the 6 notes above as well
+        if let Some(note) = text.get("base_note") {
+            if note.as_u64().is_none_or(|n| n > 121) {
+                self.report(&join(path, "base_note"), "should be a note from 0 to 121".to_string());
+            }
+        }
+        match text.get("lines") {
+            Some(Value::Array(lines)) if lines.is_empty() => {
+                self.report(&join(path, "lines"), "needs at least one line".to_string());
+            },
+            _ => {},
+        }
+        self.one_of(text.get("text_style"), &join(path, "text_style"), &TEXT_STYLES);
+        self.one_of(text.get("direction"), &join(path, "direction"), &TEXT_DIRECTIONS);
+    }
+
+    /// a string that has to be one of a few names, gives it back if it's fine
+    fn one_of(&mut self, value: Option<&Value>, path: &str, names: &[&str]) -> Option<String> {
+        match value? {
+            Value::String(s) if names.contains(&s.as_str()) => Some(s.clone()),
+            other => {
+                self.report(path, format!("unknown {} {}, should be one of {}", key_of(path), other, names.join(", ")));
+                None
+            },
+        }
+    }
+
+    fn span(&mut self, value: &Value, path: &str) {
+        let span = match value {
+            Value::Number(n) => match n.as_u64() {
+                Some(ms) => TimeSpan::Ms(ms as u32),
+                None => return self.report(path, "should be whole ms".to_string()),
+            },
+            Value::String(s) => match TimeSpan::parse(s) {
+                Ok(span) => span,
+                Err(e) => return self.report(path, e),
+            },
+            _ => return self.report(path, "should be ms or like \"1/16\"".to_string()),
+        };
+        let zero = match span {
+            TimeSpan::Ms(ms) => ms == 0,
+            TimeSpan::Note(_) => false,
+            TimeSpan::Beats(x) | TimeSpan::Bars(x) => x <= 0.0,
+        };
+        if zero {
+            self.report(path, "can't be 0".to_string());
+        }
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn key_of(path: &str) -> &str {
+    path.rsplit('.').next().unwrap_or(path)
+}
+
+fn skip_space(bytes: &[u8], mut i: usize) -> usize {
+    while bytes.get(i).map(|b| b.is_ascii_whitespace()).unwrap_or(false) {
+        i += 1;
+    }
+    i
+}
+
+/// note down where every value starts, returns where this one ends.
+/// only run on text serde_json already accepted
+fn scan(bytes: &[u8], i: usize, path: &str, spots: &mut HashMap<String, usize>) -> usize {
+    let mut i = skip_space(bytes, i);
+    spots.insert(path.to_string(), i);
+    match bytes.get(i) {
+        Some(b'{') => {
+            i += 1;
+            loop {
+                i = skip_space(bytes, i);
+                match bytes.get(i) {
+                    Some(b'}') | None => return i + 1,
+                    Some(b',') => {
+                        i += 1;
+                        continue;
+                    },
+                    _ => {},
+                }
+                let key_start = i + 1;
+                i = skip_string(bytes, i);
+                let key = String::from_utf8_lossy(&bytes[key_start..i - 1]).to_string();
+                i = skip_space(bytes, i) + 1; // the colon
+                i = scan(bytes, i, &join(path, &key), spots);
+            }
+        },
+        Some(b'[') => {
+            i += 1;
+            let mut n = 0;
+            loop {
+                i = skip_space(bytes, i);
+                match bytes.get(i) {
+                    Some(b']') | None => return i + 1,
+                    Some(b',') => {
+                        i += 1;
+                        continue;
+                    },
+                    _ => {},
+                }
+                i = scan(bytes, i, &format!("{}[{}]", path, n), spots);
+                n += 1;
+            }
+        },
+        Some(b'"') => skip_string(bytes, i),
+        _ => {
+            while let Some(b) = bytes.get(i) {
+                if matches!(b, b',' | b']' | b'}') || b.is_ascii_whitespace() {
+                    break;
+                }
+                i += 1;
+            }
+            i
+        },
+    }
+}
+
+/// from the opening quote to just after the closing one
+fn skip_string(bytes: &[u8], mut i: usize) -> usize {
+    i += 1;
+    while let Some(b) = bytes.get(i) {
+        match b {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    i
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
+
+/// how often to look at the files
+const CHECK_EVERY: Duration = Duration::from_millis(500);
+
+/// what changed on disk since last time
+pub enum Change {
+    /// or one of the files it includes
+    Setup,
+    /// just these images
+    Bitmaps(Vec<String>),
+}
+
+/// keeps an eye on the setup json (and what it includes) and every image it uses by checking when they were modified
+pub struct Watcher {
+    setup: Vec<(String, Option<SystemTime>)>,
+    bitmaps: Vec<(String, Option<SystemTime>)>,
+    last_check: Instant,
+}
+
+impl Watcher {
+    pub fn new(setup_paths: Vec<String>, bitmap_paths: Vec<String>) -> Self {
+        Watcher {
+            setup: with_times(setup_paths),
+            bitmaps: with_times(bitmap_paths),
+            last_check: Instant::now(),
+        }
+    }
+
+    /// call every frame, it only looks every so often. the setup wins if both changed
+    pub fn poll(&mut self) -> Option<Change> {
+        if self.last_check.elapsed() < CHECK_EVERY {
+            return None;
+        }
+        self.last_check = Instant::now();
+
+        let mut setup_changed = false;
+        for (path, last) in &mut self.setup {
+            let time = modified(path);
+            if time != *last {
+                *last = time;
+                setup_changed = true;
+            }
+        }
+        if setup_changed {
+            return Some(Change::Setup);
+        }
+        let mut changed = vec![];
+        for (path, last) in &mut self.bitmaps {
+            let time = modified(path);
+            if time != *last {
+                *last = time;
+                changed.push(path.clone());
+            }
+        }
+        if changed.is_empty() {
+            None
+        } else {
+            Some(Change::Bitmaps(changed))
+        }
+    }
+
+    /// forget when an image was changed so it counts as changed again next time
+    pub fn retry(&mut self, path: &str) {
+        for (bitmap, last) in &mut self.bitmaps {
+            if bitmap == path {
+                *last = None;
+            }
+        }
+    }
+}
+
+fn with_times(paths: Vec<String>) -> Vec<(String, Option<SystemTime>)> {
+    paths.into_iter().map(|path| {
+        let time = modified(&path);
+        (path, time)
+    }).collect()
+}
+
+// None if it's not there (half way through being saved, maybe)
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
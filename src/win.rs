@@ -1,58 +1,115 @@
-use minifb::{Window, WindowOptions};
+use minifb::{Window, WindowOptions, Scale, Key, KeyRepeat, MouseButton, MouseMode};
 use fontdue::Font;
 use std::cmp::min;
+use std::error::Error;
 use std::path::Path;
+use std::time::Duration;
 
 use crate::{
     graphics::*, 
-    chord::{Chord, ChordStyle}, 
+    chord::{Chord, ChordStyle, PressureStyle}, 
     midi::Note, 
-    gui::{Direction, KeyboardFocus, Inst}, 
+    gui::{Direction, KeyboardFocus, Inst, Learn}, 
     Setup, 
     drum::{Drum, DrumGfx, DrumStyle}, 
-    text::{TextDirection, Text, TextStyle}
+    text::{TextDirection, Text, TextStyle},
+    transport::Transport,
+    clock::Clock,
 };
 
+/// how long a frame lasts (the update rate is limited to this)
+pub const FRAME: Duration = Duration::from_micros(8000);
+/// how long status messages stay up
+const STATUS_TIME: Duration = Duration::from_millis(2400);
+
 pub struct Win {
     pub width: u32,
     pub height: u32,
     buffer: Vec<u32>,
-    pub window: Window,
-    pub tick: usize,
+    /// None when rendering headless
+    window: Option<Window>,
+    /// what time it is, moves on update
+    pub clock: Clock,
     main_font: Font,
     pub running: bool,
-    pub status_message: StatusMessage
+    pub status_message: StatusMessage,
+    /// what's wrong with the setup we tried to load, up until one loads
+    pub problems: Vec<String>,
+    /// where the song is at, for anything that wants to move in time with it
+    pub transport: Transport,
 }
 
 impl Win {
-    pub fn init(width: u32, height: u32) -> Self {
+    pub fn init(width: u32, height: u32, scale: Scale, clock: Clock) -> Self {
+        let mut window = Window::new(
+            "grooveanim thing idk (meows softly)",
+            width as usize,
+            height as usize,
+            WindowOptions { scale, ..WindowOptions::default() },
+        ).unwrap_or_else(|e| {
+            panic!("uhh... {}", e);
+        });
+        window.limit_update_rate(Some(clock.frame_length()));
+        let mut w = Win::headless(width, height, clock);
+        w.window = Some(window);
+        w
+    }
+
+    /// draws into the buffer without showing it anywhere
+    pub fn headless(width: u32, height: u32, clock: Clock) -> Self {
         let font = include_bytes!("rsrc/misaki_gothic_2nd.ttf") as &[u8];
         let misaki = Font::from_bytes(font, fontdue::FontSettings::default()).unwrap();
 
-        let mut w = Win {
+        Win {
             width,
             height,
             buffer: vec![0; width as usize * height as usize],
-            window: Window::new(
-                "grooveanim thing idk (meows softly)",
-                width as usize,
-                height as usize,
-                WindowOptions::default(),
-            ).unwrap_or_else(|e| {
-                panic!("uhh... {}", e);
-            }),
-            tick: 0,
+            window: None,
+            clock,
             main_font: misaki,
             running: true,
-            status_message: StatusMessage::None
-        };
-        w.window.limit_update_rate(Some(std::time::Duration::from_micros(8000)));
-        w
+            status_message: StatusMessage::None,
+            problems: vec![],
+            transport: Transport::new(),
+        }
     }
 
     pub fn update(&mut self) {
-        self.window.update_with_buffer(&self.buffer, self.width as usize, self.height as usize).unwrap();
-        self.tick += 1;
+        if let Some(window) = &mut self.window {
+            window.update_with_buffer(&self.buffer, self.width as usize, self.height as usize).unwrap();
+        }
+        self.clock.advance();
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.as_ref().map(|w| w.is_open()).unwrap_or(true)
+    }
+
+    pub fn keys_pressed(&self) -> Vec<Key> {
+        self.window.as_ref().map(|w| w.get_keys_pressed(KeyRepeat::Yes)).unwrap_or_default()
+    }
+
+    pub fn key_down(&self, key: Key) -> bool {
+        self.window.as_ref().map(|w| w.is_key_down(key)).unwrap_or(false)
+    }
+
+    /// where the mouse is while the left button's down
+    pub fn mouse_dragging(&self) -> Option<(f32, f32)> {
+        let window = self.window.as_ref()?;
+        if window.get_mouse_down(MouseButton::Left) {
+            window.get_mouse_pos(MouseMode::Clamp)
+        } else {
+            None
+        }
+    }
+
+    pub fn save_frame(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let frame = image::RgbImage::from_fn(self.width, self.height, |x, y| {
+            let pixel = self.buffer[(y * self.width + x) as usize];
+            image::Rgb([(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8])
+        });
+        frame.save(path)?;
+        Ok(())
     }
 
     pub fn clear(&mut self) {
@@ -125,14 +182,14 @@ impl Win {
         }
     }
 
-    pub fn draw_bitmap(&mut self, xpos: u32, ypos: u32, dimmed: Option<f32>, bitmap: &Bitmap) {
+    pub fn draw_bitmap(&mut self, xpos: u32, ypos: u32, dimmed: Option<f32>, tint: &Tint, bitmap: &Bitmap) {
         for y in ypos..ypos + bitmap.ysize {
             for x in xpos..xpos + bitmap.xsize {
                 let screen_pos = ((y as usize) * self.width as usize) + x as usize;
                 if screen_pos >= self.buffer.len() {
                     return;
                 }
-                let data = bitmap.color(x - xpos, y - ypos, dimmed);
+                let data = bitmap.color(x - xpos, y - ypos, dimmed, tint);
                 if data != 0 {
                     self.buffer[screen_pos] = data;
                 }
@@ -142,10 +199,14 @@ impl Win {
 
     pub fn draw_chord(&mut self, chord: &mut Chord, global_transpose: i32) {
         let mut to_remove: Vec<Note> = vec![];
+        let master = chord.master_channel();
+        let dt = self.clock.frame_time().as_secs_f32() * 1000.0;
+        let pressure_style = chord.pressure_style();
 
         for note in &mut chord.notes {
             // height of a note is 8px
             let height: u32 = 8;
+            let pressure = note.pressure as f32 / 127.0;
             // gap of 4 between each
             let effective_pitch = note.pitch as i32 + chord.transpose + global_transpose;
             if effective_pitch < 0 {
@@ -156,8 +217,8 @@ impl Win {
             }
             let mut ypos = self.height - (effective_pitch as u32 * (height + 4));
 
-            // vibrato
-            let saw = self.tick % 10;
+            // vibrato, a saw every 80ms
+            let saw = (self.clock.elapsed().as_millis() / 8 % 10) as u32;
             let wiggle = chord.modulation as f32 / 32000.0;
             if saw < 5 {
                 ypos += (wiggle * saw as f32) as u32;
@@ -165,6 +226,14 @@ impl Win {
                 ypos -= (wiggle * saw as f32) as u32;
             }
 
+            // pitch bend, same spacing as the notes so a semitone of bend is one note up
+            let bend = Chord::bend_of(&chord.bends, master, note);
+            let bent = ypos as i32 - (bend * (height + 4) as f32).round() as i32;
+            if bent < 0 || bent >= self.height as i32 {
+                continue; // bent out of range
+            }
+            ypos = bent as u32;
+
             // velocity
             let (mut color, mut shadow) = if chord.velocity_sense {
                 let _color = chord.color.dimmed(note.velocity as f32 / 127.0);
@@ -173,57 +242,100 @@ impl Win {
             }  else {
                 (chord.color, chord.shadow)
             };
+            color = chord.tint.apply(&color);
+            shadow = chord.tint.apply(&shadow);
 
             let mut xpos = chord.xpos;
             let mut width = chord.width;
 
+            // mpe slide moves each note sideways, up to half the width
+            if chord.mpe.is_some() {
+                let slide = Chord::slide_of(&chord.slides, master, note);
+                let shifted = xpos as i32 + (slide * width as f32 / 2.0).round() as i32;
+                // not off the right edge either, the marquee counts from there
+                xpos = shifted.clamp(0, self.width as i32) as u32;
+            }
+
             match chord.style {
                 ChordStyle::Plain => {},
                 ChordStyle::Decay{time} => {
+                    let time = time.ms(&self.transport);
                     if note.age >= time {
                         to_remove.push(note.clone());
                         continue;
                     } else {
-                    let f = (time - note.age) as f32 / time as f32;
+                    let f = (time - note.age) / time;
                     color = color.dimmed(f);
                     shadow = shadow.dimmed(f);
                     }
                 },
                 ChordStyle::DecayRelease{time} =>{
+                    let time = time.ms(&self.transport);
                     if note.age >= time {
                         to_remove.push(note.clone());
                         continue;
                     } else {
-                        let f = (time - note.age) as f32 / time as f32;
+                        let f = (time - note.age) / time;
                         color = color.dimmed(f);
                         shadow = shadow.dimmed(f);
                     }
                 },
                 ChordStyle::Grow{speed} => {
-                    if note.age * speed <= chord.width {
-                        width = note.age * speed;
+                    let grown = (note.age * speed.px_per_ms(&self.transport, chord.width)) as u32;
+                    if grown <= chord.width {
+                        width = grown;
                     }
                 },
                 ChordStyle::Marquee{speed} => {
-                    if note.age * speed > self.width + chord.width - xpos{
+                    // the whole trip, in from the right edge until it's gone
+                    let trip = (self.width + chord.width).saturating_sub(xpos);
+                    let travelled = (note.age * speed.px_per_ms(&self.transport, trip)) as u32;
+                    if travelled > trip {
                         to_remove.push(note.clone());
                         continue;
                     }
-                    if note.age * speed < (self.width - xpos) {
-                        xpos = self.width - note.age * speed;
+                    if travelled < self.width.saturating_sub(xpos) {
+                        xpos = self.width - travelled;
                     } else {
 
-                        width = chord.width + self.width - xpos + 1 - note.age * speed;
+                        width = (chord.width + self.width + 1).saturating_sub(xpos + travelled);
                     }
                 },
             }
 
-            if chord.style != ChordStyle::Plain {
-                note.age += 1;
+            if let ChordStyle::DecayRelease{time: _} = chord.style {
+                // only starts fading once the key (and pedals) let go
+                if !note.sounding(chord.sustain, chord.sostenuto) {
+                    note.age += dt;
+                }
+            } else if chord.style != ChordStyle::Plain {
+                note.age += dt;
                 
             }
 
-            self.draw_rect(xpos, ypos, width, height, &color);
+            // aftertouch
+            let mut bar_top = ypos;
+            let mut bar_height = height;
+            match pressure_style {
+                PressureStyle::None => {},
+                PressureStyle::Brightness => {
+                    color = color.mixed(&RGB::fff(), pressure * 0.7);
+                },
+                PressureStyle::Thickness => {
+                    // grows upwards into the gap, up to double
+                    let extra = (pressure * height as f32) as u32;
+                    bar_top = ypos.saturating_sub(extra);
+                    bar_height = height + (ypos - bar_top);
+                },
+                PressureStyle::Glow => {
+                    if note.pressure > 0 {
+                        let glow = color.scaled(pressure * 0.6);
+                        self.draw_rect(xpos.saturating_sub(3), ypos.saturating_sub(3), width + 6, height + 8, &glow);
+                    }
+                },
+            }
+
+            self.draw_rect(xpos, bar_top, width, bar_height, &color);
             self.draw_rect(xpos, ypos + height, width, 2, &shadow);
         }
 
@@ -249,24 +361,26 @@ impl Win {
 
     pub fn draw_drum(&mut self, drum: &mut Drum) {
         if drum.state.triggered {
-            if !drum.state.tick_or_reset(drum.decay_time) {
+            let decay_time = drum.decay_time.ms(&self.transport).max(1.0);
+            if !drum.state.tick_or_reset(decay_time, self.clock.frame_time().as_secs_f32() * 1000.0) {
                 return;
             }
             match &drum.gfx {
                 DrumGfx::Plain { xsize, ysize, color } => {
+                    let color = drum.tint.apply(color);
                     let color = if drum.style == DrumStyle::Decay {
-                        color.dimmed((drum.decay_time - drum.state.age) as f32 / drum.decay_time as f32)
+                        color.dimmed((decay_time - drum.state.age) / decay_time)
                     } else {
-                        *color
+                        color
                     };
                     self.draw_rect(drum.xpos, drum.ypos, *xsize, *ysize, &color)
                 },
                 DrumGfx::Bitmap { bitmap  } => {
                     if drum.style == DrumStyle::Decay {
-                        let f = (drum.decay_time - drum.state.age) as f32 / drum.decay_time as f32;
-                        self.draw_bitmap(drum.xpos, drum.ypos, Some(f), &bitmap);
+                        let f = (decay_time - drum.state.age) / decay_time;
+                        self.draw_bitmap(drum.xpos, drum.ypos, Some(f), &drum.tint, &bitmap);
                     } else {
-                        self.draw_bitmap(drum.xpos, drum.ypos, None, &bitmap);
+                        self.draw_bitmap(drum.xpos, drum.ypos, None, &drum.tint, &bitmap);
                     };
                     
                 },
@@ -328,6 +442,15 @@ impl Win {
         self.draw_hatched_rect(drum.xpos, drum.ypos, *xsize, *ysize, 2, &color);
     }
 
+    pub fn draw_ghost_text(&mut self, text: &Text) {
+        let c_white = RGB::fff();
+        let text_y = if text.ypos < 40 {text.ypos + 20} else {text.ypos - 36};
+        let text_x = min(text.xpos, self.width - 90);
+        self.draw_plaintext(text_x, text_y, 16.0, format!("ch{} x:{} y:{}", 1+text.channel as u8, text.xpos, text.ypos), TextDirection::Horizontal, &c_white);
+        let width = text.lines.iter().map(|l| self.calc_text_width(l, 16.0)).max().unwrap_or(0) as u32;
+        self.draw_outline_rect(text.xpos, text.ypos, width.max(16), 18, 2, true, &text.color);
+    }
+
     pub fn draw_plaintext_fw(&mut self, xpos: u32, ypos: u32, text: String, color: &RGB) {
         let mut position = 0;
         let size = 16.0;
@@ -456,26 +579,26 @@ impl Win {
             },
             
         };
-        self.draw_plaintext(text.xpos, text.ypos, 16.0, sliced, text.direction, &text.color);
+        let color = text.tint.apply(&text.color);
+        self.draw_plaintext(text.xpos, text.ypos, 16.0, sliced, text.direction, &color);
     }
 
     pub fn draw_status_message(&mut self) {
-        let time = 300;
         let (since, text, color) = match &self.status_message {
             StatusMessage::None => {
                 return;
             },
             StatusMessage::Some { text, since, color } => {
-                if since + time < self.tick {
+                if *since + STATUS_TIME < self.clock.elapsed() {
                     // done showing
                     self.status_message = StatusMessage::None;
                     return;
                 } else {
-                    (since, text.to_string(), color)
+                    (*since, text.to_string(), color)
                 }
             },
         };
-        let f = (time - (self.tick - since)) as f32 / time as f32 * 3.0;
+        let f = (STATUS_TIME - (self.clock.elapsed() - since)).as_secs_f32() / STATUS_TIME.as_secs_f32() * 3.0;
         let ypos = self.height - 22;
         let width = self.calc_text_width(&text, 16.0) as u32;
         let main_color = color.dimmed(f);
@@ -487,10 +610,23 @@ impl Win {
 
     }
 
+    pub fn draw_problems(&mut self) {
+        let lines = self.problems.clone();
+        let shown = (self.height as usize / 16).saturating_sub(3);
+        self.draw_rect(0, 0, self.width, (lines.len().min(shown) as u32 + 1) * 16, &RGB::new(0, 0, 0));
+        for (i, line) in lines.iter().take(shown).enumerate() {
+            self.draw_plaintext(2, i as u32 * 16, 16.0, line.to_string(), TextDirection::Horizontal, &RGB::color("red"));
+        }
+        if lines.len() > shown {
+            let more = format!("..and {} more, see console", lines.len() - shown);
+            self.draw_plaintext(2, shown as u32 * 16, 16.0, more, TextDirection::Horizontal, &RGB::color("red"));
+        }
+    }
+
     pub fn set_status_message(&mut self, message: &str, color: RGB) {
         self.status_message = StatusMessage::Some { 
             text: message.to_string(), 
-            since: self.tick,
+            since: self.clock.elapsed(),
             color
         };
     }
@@ -503,11 +639,11 @@ impl Win {
             Direction::Up => todo!(),
             Direction::Down => {
                 let down_arrow = arrow.rotated();
-                self.draw_bitmap(xpos, ypos, None, &down_arrow);
+                self.draw_bitmap(xpos, ypos, None, &Tint::default(), &down_arrow);
             },
             Direction::Left => todo!(),
             Direction::Right => {
-                self.draw_bitmap(xpos, ypos, None, &arrow);
+                self.draw_bitmap(xpos, ypos, None, &Tint::default(), &arrow);
             },
         }
     }
@@ -559,9 +695,51 @@ impl Win {
 
                 self.draw_plaintext_fw(xpos, 16, format!("Drum {: >2}", focus.index+1), &if has{RGB::fff()} else{RGB::color("red")});
             },
+            Inst::Text => {
+                let has = match focus.get_focused_text(&setup.texts) {
+                    Some(i) => {
+                        self.draw_ghost_text(&setup.texts[i]);
+                        if setup.texts[i].xpos < 70 && setup.texts[i].ypos < 100 {
+                            xpos = right_xpos;
+                        }
+                        true
+                    },
+                    None => false
+                };
+                if focus.preview_all {
+                    for text in &setup.texts {
+                        self.draw_ghost_text(text);
+                        if text.xpos < 70 && text.ypos < 100 {
+                            xpos = right_xpos;
+                        }
+                    }
+                }
+                self.draw_plaintext_fw(xpos, 16, format!("Text {: >2}", focus.index+1), &if has{RGB::fff()} else{RGB::color("red")});
+            },
         }
         self.draw_plaintext_fw(xpos, 1, "Editing".to_string(), &RGB::fff());
+        match focus.learning {
+            Some(Learn::Note) => self.draw_plaintext_fw(xpos, 31, "Learn nt".to_string(), &RGB::color("red")),
+            Some(Learn::Cc) => self.draw_plaintext_fw(xpos, 31, "Learn cc".to_string(), &RGB::color("red")),
+            None => {},
+        }
+        self.draw_transport();
+
+    }
 
+    fn draw_transport(&mut self) {
+        let tempo = match self.transport.bpm {
+            Some(bpm) => format!("{: >3.0}bpm", bpm),
+            None => "---bpm".to_string(),
+        };
+        let text = format!("{} {: >3}.{}", tempo, self.transport.bar() + 1, self.transport.beat_in_bar() + 1);
+        // flash on the beat
+        let color = if self.transport.playing {
+            RGB::fff().dimmed(1.0 - self.transport.beat_phase(self.clock.now()))
+        } else {
+            RGB::fff().dimmed(0.3)
+        };
+        self.draw_plaintext_fw(self.width - 110, self.height - 18, text, &color);
     }
 
 
@@ -572,7 +750,7 @@ pub enum StatusMessage {
     None,
     Some {
         text: String,
-        since: usize,
+        since: Duration,
         color: RGB
     }
 }